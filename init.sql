@@ -6,6 +6,8 @@ CREATE TABLE `file_block` (
   `block_size` int unsigned NOT NULL COMMENT '块体积Bytes',
   `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT 'CreatedAt',
   `block_name` varchar(255) NOT NULL COMMENT '块文件名',
+  `block_status` int NOT NULL DEFAULT 0 COMMENT '0:正常,1:损坏,2:丢失',
+  `checked_at` datetime DEFAULT NULL COMMENT '最近一次校验时间',
   PRIMARY KEY (`id`),
   KEY `idx_file_id_block_id` (`file_id`,`block_id`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='块元数据';
@@ -16,14 +18,69 @@ CREATE TABLE `file_info` (
   `file_name` varchar(255) NOT NULL COMMENT '文件名',
   `file_checksum` int unsigned NOT NULL COMMENT '文件描述',
   `file_size` bigint NOT NULL COMMENT '文件体积Bytes',
-  `file_status` int NOT NULL COMMENT '0:未完成,1:已完成,2:已删除',
-  PRIMARY KEY (`id`)
+  `file_status` int NOT NULL COMMENT '0:未完成,1:已完成,2:已删除,3:历史版本',
+  `data_key` varbinary(128) DEFAULT NULL COMMENT '被主密钥包裹的数据密钥, NULL表示明文存储',
+  `dir_id` int NOT NULL DEFAULT 0 COMMENT '所在目录id, 0为根目录',
+  `owner` varchar(255) DEFAULT NULL COMMENT '上传者用户名',
+  `mime_type` varchar(255) DEFAULT NULL COMMENT 'MIME类型',
+  `description` text COMMENT '文件描述',
+  `logical_id` int NOT NULL DEFAULT 0 COMMENT '逻辑文件id, 同一文件的各版本相同',
+  `version` int NOT NULL DEFAULT 1 COMMENT '版本号',
+  `deleted_at` datetime DEFAULT NULL COMMENT '删除时间',
+  `deleted_by` varchar(255) DEFAULT NULL COMMENT '删除者用户名',
+  `stored_size` bigint DEFAULT NULL COMMENT '完成上传时计入配额的实际字节数',
+  `version_key` int GENERATED ALWAYS AS (NULLIF(`logical_id`, 0)) STORED INVISIBLE COMMENT '新建文件回填 logical_id 前为 NULL, 用于版本号唯一约束',
+  `active_name` varchar(255) GENERATED ALWAYS AS (IF(`file_status` = 1, `file_name`, NULL)) STORED INVISIBLE COMMENT '当前版本的文件名, 用于目录内唯一约束',
+  PRIMARY KEY (`id`),
+  KEY `idx_file_status_deleted_at` (`file_status`,`deleted_at`),
+  KEY `idx_file_status_file_name` (`file_status`,`file_name`,`id`),
+  KEY `idx_file_status_file_size` (`file_status`,`file_size`,`id`),
+  KEY `idx_file_status_created_at` (`file_status`,`created_at`,`id`),
+  FULLTEXT KEY `ft_file_name` (`file_name`) WITH PARSER ngram,
+  FULLTEXT KEY `ft_description` (`description`) WITH PARSER ngram,
+  KEY `idx_logical_id_version` (`logical_id`,`version`),
+  KEY `idx_dir_id_file_name` (`dir_id`,`file_name`),
+  KEY `idx_owner_file_status` (`owner`,`file_status`),
+  UNIQUE KEY `uk_dir_id_active_name` (`dir_id`,`active_name`),
+  UNIQUE KEY `uk_version_key_version` (`version_key`,`version`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='文件元数据';
 
+CREATE TABLE `file_tag` (
+  `id` int NOT NULL AUTO_INCREMENT COMMENT 'id',
+  `file_id` int NOT NULL COMMENT 'file_info id',
+  `tag_key` varchar(255) NOT NULL COMMENT '标签键',
+  `tag_value` varchar(255) NOT NULL COMMENT '标签值',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT 'CreatedAt',
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `uk_file_id_tag_key` (`file_id`,`tag_key`),
+  KEY `idx_tag_key_tag_value` (`tag_key`,`tag_value`),
+  FULLTEXT KEY `ft_tag` (`tag_key`,`tag_value`) WITH PARSER ngram
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='文件标签';
+
+CREATE TABLE `directory` (
+  `id` int NOT NULL AUTO_INCREMENT COMMENT 'id',
+  `parent_id` int NOT NULL DEFAULT 0 COMMENT '父目录id, 0为根目录',
+  `dir_name` varchar(255) NOT NULL COMMENT '目录名',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT 'CreatedAt',
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `uk_parent_id_dir_name` (`parent_id`,`dir_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='目录';
+
+CREATE TABLE `user_quota` (
+  `user_name` varchar(255) NOT NULL COMMENT '用户名',
+  `max_bytes` bigint DEFAULT NULL COMMENT '容量上限Bytes, NULL表示使用默认配额',
+  `max_files` int DEFAULT NULL COMMENT '文件数上限, NULL表示使用默认配额',
+  `used_bytes` bigint NOT NULL DEFAULT 0 COMMENT '已用容量Bytes',
+  `file_count` int NOT NULL DEFAULT 0 COMMENT '已存文件数, 每个版本单独计数',
+  PRIMARY KEY (`user_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='用户配额与用量';
+
 CREATE TABLE `user` (
   `id` int NOT NULL AUTO_INCREMENT COMMENT 'id',
   `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT 'CreatedAt',
   `user_name` varchar(255) NOT NULL COMMENT '用户名',
   `user_password` varchar(255) NOT NULL COMMENT '用户密码',
-  PRIMARY KEY (`id`)
+  `cert_subject` varchar(255) DEFAULT NULL COMMENT '客户端证书CN或SAN, 用于mTLS登录',
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `uk_cert_subject` (`cert_subject`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='用户表';
\ No newline at end of file
@@ -0,0 +1,203 @@
+use std::{env, fs, str::FromStr};
+
+use base64::{Engine, engine::general_purpose};
+use lazy_static::lazy_static;
+use log::*;
+
+pub struct Config {
+    pub master_key: Option<[u8; 32]>,
+    pub admin_users: Vec<String>,
+    pub scrub_interval_secs: u64,
+    pub scrub_bytes_per_sec: u64,
+    pub version_keep_last: u32,
+    pub version_keep_days: u64,
+    pub trash_retention_days: u64,
+    pub default_quota_bytes: i64,
+    pub default_quota_files: i32,
+    pub max_frame_size: usize,
+    pub max_block_size: usize,
+    pub max_file_size: u64,
+    pub request_rate_per_sec: f64,
+    pub request_burst: f64,
+    pub login_rate_per_min: f64,
+    pub login_burst: f64,
+    pub login_max_failures: u32,
+    pub login_lockout_secs: u64,
+    pub login_max_lockout_secs: u64,
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub method_concurrency: Vec<(String, usize)>,
+    pub queue_timeout_ms: u64,
+    pub client_ca_file: Option<String>,
+    // none / optional / required
+    pub client_auth: String,
+    pub cert_reload_secs: u64,
+    pub tls_min_version: Option<String>,
+    pub tls_ciphers: Option<String>,
+    pub tls_ciphersuites: Option<String>,
+    pub tls_groups: Option<String>,
+    pub tls_session_tickets: bool,
+    pub tls_session_cache: bool,
+    pub tls_alpn: Vec<String>,
+    // (server_name, private_key_file, cert_file)
+    pub tls_sni_certs: Vec<(String, String, String)>,
+    pub dev_mode: bool,
+    pub dev_cert_sans: Vec<String>,
+    pub listeners: Vec<String>,
+    pub unix_socket_mode: u32,
+    pub trusted_proxies: Vec<String>,
+    pub ws_allowed_origins: Vec<String>,
+    pub event_history: usize,
+    pub quota_warn_percent: u32,
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Config {
+            master_key: load_master_key(),
+            admin_users: env_list("ADMIN_USERS"),
+            scrub_interval_secs: env_or("SCRUB_INTERVAL_SECS", 24 * 3600),
+            scrub_bytes_per_sec: env_or("SCRUB_BYTES_PER_SEC", 16 * 1024 * 1024),
+            version_keep_last: env_or("VERSION_KEEP_LAST", 0),
+            version_keep_days: env_or("VERSION_KEEP_DAYS", 0),
+            trash_retention_days: env_or("TRASH_RETENTION_DAYS", 30),
+            default_quota_bytes: env_or("DEFAULT_QUOTA_BYTES", 0),
+            default_quota_files: env_or("DEFAULT_QUOTA_FILES", 0),
+            max_frame_size: env_or("MAX_FRAME_SIZE", 64 * 1024 * 1024),
+            max_block_size: env_or("MAX_BLOCK_SIZE", 8 * 1024 * 1024),
+            max_file_size: env_or("MAX_FILE_SIZE", 0),
+            request_rate_per_sec: env_or("REQUEST_RATE_PER_SEC", 50.0),
+            request_burst: env_or("REQUEST_BURST", 100.0),
+            login_rate_per_min: env_or("LOGIN_RATE_PER_MIN", 10.0),
+            login_burst: env_or("LOGIN_BURST", 5.0),
+            login_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            login_lockout_secs: env_or("LOGIN_LOCKOUT_SECS", 30),
+            login_max_lockout_secs: env_or("LOGIN_MAX_LOCKOUT_SECS", 3600),
+            max_connections: env_or("MAX_CONNECTIONS", 1024),
+            max_connections_per_ip: env_or("MAX_CONNECTIONS_PER_IP", 64),
+            method_concurrency: load_method_concurrency(),
+            queue_timeout_ms: env_or("QUEUE_TIMEOUT_MS", 5000),
+            client_ca_file: env::var("CLIENT_CA_FILE").ok(),
+            client_auth: env_or("CLIENT_AUTH", "none".to_string()),
+            cert_reload_secs: env_or("CERT_RELOAD_SECS", 60),
+            tls_min_version: env::var("TLS_MIN_VERSION").ok().map(|version| version.trim().to_string()),
+            tls_ciphers: env::var("TLS_CIPHERS").ok(),
+            tls_ciphersuites: env::var("TLS_CIPHERSUITES").ok(),
+            tls_groups: env::var("TLS_GROUPS").ok(),
+            tls_session_tickets: env_or("TLS_SESSION_TICKETS", true),
+            tls_session_cache: env_or("TLS_SESSION_CACHE", true),
+            tls_alpn: env_list("TLS_ALPN"),
+            tls_sni_certs: load_sni_certs(),
+            // 逗号分隔, 如 "127.0.0.1:17878,[::1]:17878,unix:/run/rfs.sock,unix+tls:/run/rfs-tls.sock,https://0.0.0.0:8443"
+            listeners: env_list("LISTEN"),
+            unix_socket_mode: env::var("UNIX_SOCKET_MODE")
+                .map(|mode| {
+                    u32::from_str_radix(mode.trim(), 8).unwrap_or_else(|_| panic!("invalid value for UNIX_SOCKET_MODE: {mode}"))
+                })
+                .unwrap_or(0o600),
+            trusted_proxies: env_list("TRUSTED_PROXIES"),
+            // 如 "https://app.example.com", 为空时只允许与 Host 相同的来源
+            ws_allowed_origins: env_list("WS_ALLOWED_ORIGINS"),
+            dev_mode: env_or("DEV_MODE", false),
+            dev_cert_sans: match env_list("DEV_CERT_SANS") {
+                sans if sans.is_empty() => vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()],
+                sans => sans,
+            },
+            event_history: env_or("EVENT_HISTORY", 1024),
+            quota_warn_percent: env_or("QUOTA_WARN_PERCENT", 90),
+        }
+    }
+
+    pub fn is_admin(&self, user_name: &str) -> bool {
+        self.admin_users.iter().any(|admin| admin == user_name)
+    }
+}
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::from_env();
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid value for {name}: {value}")),
+        Err(_) => default,
+    }
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// METHOD_CONCURRENCY: 逗号分隔的 method=n, 如 "send=32,download_file=16"
+fn load_method_concurrency() -> Vec<(String, usize)> {
+    if env::var("METHOD_CONCURRENCY").is_err() {
+        return vec![("send".to_string(), 32)];
+    }
+
+    env_list("METHOD_CONCURRENCY")
+        .into_iter()
+        .map(|item| {
+            item.split_once('=')
+                .and_then(|(method, n)| Some((method.trim().to_string(), n.trim().parse().ok()?)))
+                .unwrap_or_else(|| panic!("invalid value for METHOD_CONCURRENCY: {item}"))
+        })
+        .collect()
+}
+
+// TLS_SNI_CERTS: 逗号分隔的 host:key_file:cert_file
+fn load_sni_certs() -> Vec<(String, String, String)> {
+    env_list("TLS_SNI_CERTS")
+        .into_iter()
+        .map(|item| {
+            let parts: Vec<&str> = item.split(':').map(str::trim).collect();
+            match parts[..] {
+                [host, key, cert] => (host.to_string(), key.to_string(), cert.to_string()),
+                _ => panic!("invalid value for TLS_SNI_CERTS: {item}"),
+            }
+        })
+        .collect()
+}
+
+// MASTER_KEY: base64 编码的 32 字节密钥; MASTER_KEY_FILE: 原始 32 字节或 base64 文本
+// 配置了但无法解析时直接退出, 避免静默回退到明文存储
+fn load_master_key() -> Option<[u8; 32]> {
+    if let Ok(key) = env::var("MASTER_KEY") {
+        return Some(parse_key(key.trim().as_bytes()).unwrap_or_else(|e| panic!("invalid MASTER_KEY: {e}")));
+    }
+
+    if let Ok(path) = env::var("MASTER_KEY_FILE") {
+        return Some(read_key_file(&path).unwrap_or_else(|e| panic!("invalid MASTER_KEY_FILE: {e}")));
+    }
+
+    warn!("no MASTER_KEY or MASTER_KEY_FILE configured, blocks will be stored in plaintext");
+    None
+}
+
+pub fn read_key_file(path: &str) -> Result<[u8; 32], String> {
+    let data = fs::read(path).map_err(|e| format!("read {path} err: {e}"))?;
+    parse_key(&data)
+}
+
+fn parse_key(data: &[u8]) -> Result<[u8; 32], String> {
+    if let Ok(key) = <[u8; 32]>::try_from(data) {
+        return Ok(key);
+    }
+
+    let text = String::from_utf8_lossy(data);
+    let decoded = general_purpose::STANDARD
+        .decode(text.trim())
+        .map_err(|e| format!("key is neither 32 raw bytes nor base64: {e}"))?;
+    <[u8; 32]>::try_from(decoded.as_slice())
+        .map_err(|_| format!("key must be 32 bytes, got {}", decoded.len()))
+}
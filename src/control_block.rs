@@ -4,6 +4,8 @@ use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, deco
 use log::*;
 use serde::{Deserialize, Serialize};
 
+use crate::engine::{context, return_code::ErrorCode};
+
 // Header of Reqs
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ControlBlock {
@@ -22,6 +24,18 @@ impl ControlBlock {
         }
     }
 
+    pub fn check_jwt(&self) -> Result<(), String> {
+        match self.validate_jwt() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("{}: jwt expired", ErrorCode::Unauthorized)),
+            Err(e) => Err(format!("{}: invalid jwt: {e}", ErrorCode::Unauthorized)),
+        }
+    }
+
+    pub fn user_name(&self) -> Result<String, jsonwebtoken::errors::Error> {
+        Ok(validate_jwt(&self.jwt)?.user_name)
+    }
+
     pub fn refresh_jwt(&mut self) -> Result<(), jsonwebtoken::errors::Error> {
         (self.jwt, self.exp) = refresh_jwt(&self.jwt)?;
         Ok(())
@@ -152,6 +166,11 @@ where
     } else {
         ControlBlock::default()
     };
+    // 未携带 jwt 但客户端证书已映射到用户时, 以证书身份签发
+    let control_block = match context::current().and_then(|context| context.cert_user) {
+        Some(user_name) if control_block.jwt.is_empty() => ControlBlock::from_user_name(&user_name),
+        _ => control_block,
+    };
     let content: T = match serde_json::from_str(&payload) {
         Ok(content) => content,
         Err(e) => {
@@ -0,0 +1,121 @@
+use openssl::{
+    error::ErrorStack,
+    rand::rand_bytes,
+    symm::{Cipher, decrypt_aead, encrypt_aead},
+};
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+// 密文布局: nonce(12) || ciphertext || tag(16)
+pub fn seal(key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        aad,
+        plaintext,
+        &mut tag,
+    )?;
+
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len() + TAG_LEN);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    Ok(sealed)
+}
+
+pub fn open(key: &[u8; 32], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err("sealed data too short".to_string());
+    }
+
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    decrypt_aead(Cipher::aes_256_gcm(), key, Some(nonce), aad, ciphertext, tag)
+        .map_err(|e| format!("decrypt err: {e}"))
+}
+
+pub fn generate_data_key() -> Result<[u8; 32], ErrorStack> {
+    let mut key = [0u8; 32];
+    rand_bytes(&mut key)?;
+    Ok(key)
+}
+
+pub fn wrap_data_key(master_key: &[u8; 32], data_key: &[u8; 32]) -> Result<Vec<u8>, ErrorStack> {
+    seal(master_key, b"data_key", data_key)
+}
+
+pub fn unwrap_data_key(master_key: &[u8; 32], wrapped: &[u8]) -> Result<[u8; 32], String> {
+    let key = open(master_key, b"data_key", wrapped)?;
+    <[u8; 32]>::try_from(key.as_slice()).map_err(|_| "invalid data key length".to_string())
+}
+
+pub fn block_aad(file_id: u32, block_id: u64) -> Vec<u8> {
+    format!("{file_id}:{block_id}").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_open_round_trip() {
+        let key = generate_data_key().unwrap();
+        let aad = block_aad(1, 2);
+        let sealed = seal(&key, &aad, b"hello block").unwrap();
+        assert_eq!(sealed.len(), NONCE_LEN + b"hello block".len() + TAG_LEN);
+        assert_eq!(open(&key, &aad, &sealed).unwrap(), b"hello block");
+    }
+
+    #[test]
+    fn tampered_ciphertext_or_tag_rejected() {
+        let key = generate_data_key().unwrap();
+        let aad = block_aad(1, 2);
+        let sealed = seal(&key, &aad, b"hello block").unwrap();
+
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(open(&key, &aad, &tampered).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&key, &aad, &tampered).is_err());
+
+        assert!(open(&key, &aad, &sealed[..NONCE_LEN + TAG_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn wrong_aad_rejected() {
+        let key = generate_data_key().unwrap();
+        let sealed = seal(&key, &block_aad(1, 2), b"hello block").unwrap();
+        assert!(open(&key, &block_aad(1, 3), &sealed).is_err());
+        assert!(open(&key, &block_aad(2, 2), &sealed).is_err());
+    }
+
+    #[test]
+    fn wrap_unwrap_and_rotate_data_key() {
+        let master_key = generate_data_key().unwrap();
+        let data_key = generate_data_key().unwrap();
+        let wrapped = wrap_data_key(&master_key, &data_key).unwrap();
+        assert_eq!(unwrap_data_key(&master_key, &wrapped).unwrap(), data_key);
+
+        // 轮换主密钥: 用旧密钥解开后以新密钥重新包裹
+        let new_master_key = generate_data_key().unwrap();
+        let rewrapped = wrap_data_key(&new_master_key, &unwrap_data_key(&master_key, &wrapped).unwrap()).unwrap();
+        assert_eq!(unwrap_data_key(&new_master_key, &rewrapped).unwrap(), data_key);
+        assert!(unwrap_data_key(&master_key, &rewrapped).is_err());
+    }
+
+    #[test]
+    fn unwrap_with_wrong_master_key_fails() {
+        let data_key = generate_data_key().unwrap();
+        let wrapped = wrap_data_key(&generate_data_key().unwrap(), &data_key).unwrap();
+        assert!(unwrap_data_key(&generate_data_key().unwrap(), &wrapped).is_err());
+    }
+}
@@ -1,10 +1,15 @@
 use serde::{Deserialize, Serialize};
-use sqlx::{mysql::MySqlPoolOptions, MySql, Pool, Row};
+use sqlx::{mysql::MySqlPoolOptions, MySql, Pool, QueryBuilder, Row};
 use chrono::NaiveDateTime;
 use tokio::sync::OnceCell;
 
 use crate::MYSQL_URL;
 
+pub const FILE_UPLOADING: i32 = 0;
+pub const FILE_COMPLETED: i32 = 1;
+pub const FILE_DELETED: i32 = 2;
+pub const FILE_SUPERSEDED: i32 = 3;
+
 pub struct SqlManipulator {
     pool: Pool<MySql>
 }
@@ -23,16 +28,39 @@ impl SqlManipulator {
         }
     }
 
-    pub async fn init_file_info(&self, file_name: &str, file_size: u64) -> Result<u32, sqlx::Error> {
+    // version_of: 作为该逻辑文件的新版本上传, 为空时新建逻辑文件, 版本号为 1
+    pub async fn init_file_info(&self, file_name: &str, file_size: u64, data_key: Option<&[u8]>, dir_id: i32, owner: &str, version_of: Option<i32>) -> Result<u32, sqlx::Error> {
         // 开启一个事务
         let mut tx = self.pool.begin().await?;
 
+        // 锁住已有版本后再分配版本号, 避免并发上传得到相同的版本号
+        let (logical_id, version) = match version_of {
+            Some(logical_id) if logical_id <= 0 => {
+                return Err(sqlx::Error::Protocol(format!("invalid logical_id {logical_id}")));
+            }
+            Some(logical_id) => {
+                let max_version: Option<i32> = sqlx::query_scalar(
+                    "SELECT MAX(version) FROM file_info WHERE logical_id = ? FOR UPDATE",
+                )
+                .bind(logical_id)
+                .fetch_one(&mut *tx)
+                .await?;
+                (logical_id, max_version.unwrap_or(0) + 1)
+            }
+            None => (0, 1),
+        };
+
         // 执行插入操作
         sqlx::query(
-            "INSERT INTO file_info (file_name, file_size, file_checksum, file_status) VALUES (?, ?, 0, 0)",
+            "INSERT INTO file_info (file_name, file_size, file_checksum, file_status, data_key, dir_id, owner, logical_id, version) VALUES (?, ?, 0, 0, ?, ?, ?, ?, ?)",
         )
         .bind(file_name)
         .bind(file_size)
+        .bind(data_key)
+        .bind(dir_id)
+        .bind(owner)
+        .bind(logical_id)
+        .bind(version)
         .execute(&mut *tx)
         .await?;
 
@@ -40,10 +68,18 @@ impl SqlManipulator {
         let id = sqlx::query("SELECT LAST_INSERT_ID() as id")
             .fetch_one(&mut *tx)
             .await?;
+        let id: u32 = id.get("id");
+
+        if version_of.is_none() {
+            sqlx::query("UPDATE file_info SET logical_id = id WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
 
         tx.commit().await?;
 
-        Ok(id.get("id"))
+        Ok(id)
     }
 
     pub async fn write_block_info(&self, file_id: u32, block_id: u64, block_name: &str, block_size: u32, block_checksum: u32) -> Result<(), sqlx::Error> {
@@ -59,25 +95,225 @@ impl SqlManipulator {
         Ok(())
     }
 
+    // 完成上传, 同一逻辑文件之前的当前版本变为历史版本, 并把实际字节数计入所有者用量
     pub async fn finish_file_info(&self, file_id: u32, check_sum: u32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let file = sqlx::query!(
+            "SELECT logical_id, owner FROM file_info WHERE id = ? AND file_status = 0 FOR UPDATE",
+            file_id,
+        ).fetch_one(&mut *tx)
+        .await?;
+        // logical_id 未回填的旧记录会把其他文件当作历史版本
+        if file.logical_id <= 0 {
+            return Err(sqlx::Error::Protocol(format!("file {file_id} has no logical_id")));
+        }
+        let stored_size = sqlx::query_scalar!(
+            "SELECT CAST(COALESCE(SUM(block_size), 0) AS SIGNED) FROM file_block WHERE file_id = ?",
+            file_id,
+        ).fetch_one(&mut *tx)
+        .await?;
+        sqlx::query_scalar!(
+            "UPDATE file_info SET file_status = 3 WHERE logical_id = ? AND file_status = 1 AND id <> ?",
+            file.logical_id,
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
         sqlx::query_scalar!(
-            "UPDATE file_info SET file_status = 1, file_checksum = ? WHERE id = ?",
+            "UPDATE file_info SET file_status = 1, file_checksum = ?, stored_size = ? WHERE id = ?",
             check_sum,
+            stored_size,
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
+        if let Some(owner) = file.owner {
+            sqlx::query_scalar!(
+                "INSERT INTO user_quota (user_name, used_bytes, file_count) VALUES (?, ?, 1) ON DUPLICATE KEY UPDATE used_bytes = used_bytes + VALUES(used_bytes), file_count = file_count + 1",
+                owner,
+                stored_size,
+            ).execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // 用户未完成的上传: (预留字节数, 文件数), 每个上传按声明大小与已写入字节数中的较大者计算
+    pub async fn get_pending_uploads(&self, owner: &str) -> Result<(i64, i32), sqlx::Error> {
+        let row = sqlx::query!(
+            r#"SELECT
+                CAST(COALESCE(SUM(GREATEST(f.file_size, (SELECT COALESCE(SUM(b.block_size), 0) FROM file_block b WHERE b.file_id = f.id))), 0) AS SIGNED) AS "bytes!: i64",
+                CAST(COUNT(*) AS SIGNED) AS "files!: i64"
+            FROM file_info f WHERE f.owner = ? AND f.file_status = 0"#,
+            owner,
+        ).fetch_one(&self.pool)
+        .await?;
+        Ok((row.bytes, row.files as i32))
+    }
+
+    pub async fn get_uploaded_bytes(&self, file_id: i32) -> Result<i64, sqlx::Error> {
+        let bytes = sqlx::query_scalar!(
+            "SELECT CAST(COALESCE(SUM(block_size), 0) AS SIGNED) FROM file_block WHERE file_id = ?",
             file_id,
+        ).fetch_one(&self.pool)
+        .await?;
+        Ok(bytes)
+    }
+
+    pub async fn get_user_quota(&self, user_name: &str) -> Result<Option<UserQuota>, sqlx::Error> {
+        let quota = sqlx::query_as!(
+            UserQuota,
+            "SELECT * FROM user_quota WHERE user_name = ?",
+            user_name,
+        ).fetch_optional(&self.pool)
+        .await?;
+        Ok(quota)
+    }
+
+    pub async fn set_user_quota(&self, user_name: &str, max_bytes: Option<i64>, max_files: Option<i32>) -> Result<(), sqlx::Error> {
+        sqlx::query_scalar!(
+            "INSERT INTO user_quota (user_name, max_bytes, max_files) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE max_bytes = VALUES(max_bytes), max_files = VALUES(max_files)",
+            user_name,
+            max_bytes,
+            max_files,
         ).execute(&self.pool)
         .await?;
         Ok(())
     }
+
+    // 存在历史版本的逻辑文件, 供后台按保留策略清理
+    pub async fn get_versioned_logical_ids(&self) -> Result<Vec<i32>, sqlx::Error> {
+        let ids = sqlx::query_scalar!(
+            "SELECT DISTINCT logical_id FROM file_info WHERE file_status = 3 AND logical_id > 0",
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(ids)
+    }
+
+    pub async fn get_file_versions(&self, logical_id: i32) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let versions = sqlx::query_as!(
+            FileInfo,
+            "SELECT * FROM file_info WHERE logical_id = ? AND file_status IN (1, 3) ORDER BY version DESC",
+            logical_id,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(versions)
+    }
+
+    // 恢复历史版本: 沿用当前版本的名字和目录, 当前版本变为历史版本
+    pub async fn restore_version(&self, version_id: i32, logical_id: i32, dir_id: i32, file_name: &str) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query_scalar!(
+            "UPDATE file_info SET file_status = 3 WHERE logical_id = ? AND file_status = 1",
+            logical_id,
+        ).execute(&mut *tx)
+        .await?;
+        sqlx::query_scalar!(
+            "UPDATE file_info SET file_status = 1, dir_id = ?, file_name = ? WHERE id = ?",
+            dir_id,
+            file_name,
+            version_id,
+        ).execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // 彻底删除文件记录并归还配额, 块文件需由调用方先行删除
+    pub async fn delete_file_records(&self, file_id: i32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let file = sqlx::query!(
+            "SELECT owner, stored_size FROM file_info WHERE id = ? FOR UPDATE",
+            file_id,
+        ).fetch_one(&mut *tx)
+        .await?;
+        if let (Some(owner), Some(stored_size)) = (file.owner, file.stored_size) {
+            sqlx::query_scalar!(
+                "UPDATE user_quota SET used_bytes = GREATEST(used_bytes - ?, 0), file_count = GREATEST(file_count - 1, 0) WHERE user_name = ?",
+                stored_size,
+                owner,
+            ).execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query_scalar!(
+            "DELETE FROM file_block WHERE file_id = ?",
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
+        sqlx::query_scalar!(
+            "DELETE FROM file_tag WHERE file_id = ?",
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
+        sqlx::query_scalar!(
+            "DELETE FROM file_info WHERE id = ?",
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
     
-    pub async fn delete_file_info(&self, file_id: i32) -> Result<(), sqlx::Error> {
+    pub async fn delete_file_info(&self, file_id: i32, deleted_by: &str) -> Result<(), sqlx::Error> {
         sqlx::query_scalar!(
-            "UPDATE file_info SET file_status = 2 WHERE id = ?",
+            "UPDATE file_info SET file_status = 2, deleted_at = NOW(), deleted_by = ? WHERE id = ?",
+            deleted_by,
             file_id,
         ).execute(&self.pool)
         .await?;
         Ok(())
     }
 
+    // 文件在回收站期间恢复过历史版本时, 同一逻辑文件已有当前版本, 此时恢复为历史版本
+    // 返回是否恢复为当前版本
+    pub async fn restore_file_info(&self, file_id: i32, logical_id: i32, dir_id: i32) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let current = sqlx::query_scalar!(
+            "SELECT id FROM file_info WHERE logical_id = ? AND file_status = 1 FOR UPDATE",
+            logical_id,
+        ).fetch_all(&mut *tx)
+        .await?;
+        let file_status = if current.is_empty() { FILE_COMPLETED } else { FILE_SUPERSEDED };
+        sqlx::query_scalar!(
+            "UPDATE file_info SET file_status = ?, deleted_at = NULL, deleted_by = NULL, dir_id = ? WHERE id = ? AND file_status = 2",
+            file_status,
+            dir_id,
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(current.is_empty())
+    }
+
+    pub async fn get_trash(&self, user_name: &str) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let files = sqlx::query_as!(
+            FileInfo,
+            "SELECT * FROM file_info WHERE file_status = 2 AND (owner = ? OR deleted_by = ?) ORDER BY deleted_at DESC",
+            user_name,
+            user_name,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(files)
+    }
+
+    pub async fn get_all_trash(&self) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let files = sqlx::query_as!(
+            FileInfo,
+            "SELECT * FROM file_info WHERE file_status = 2 ORDER BY deleted_at DESC",
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(files)
+    }
+
+    pub async fn get_expired_trash(&self, retention_days: u64) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let files = sqlx::query_as!(
+            FileInfo,
+            "SELECT * FROM file_info WHERE file_status = 2 AND deleted_at < NOW() - INTERVAL ? DAY",
+            retention_days,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(files)
+    }
+
     pub async fn register(&self, user_name: &str, password: &str) -> Result<(), sqlx::Error> {
         sqlx::query_scalar!(
             "INSERT INTO user (user_name, user_password) VALUES (?, ?)",
@@ -98,12 +334,120 @@ impl SqlManipulator {
         Ok(count > 0)
     }
 
-    pub async fn get_file_ids(&self) -> Result<Vec<i32>, sqlx::Error> {
-        let file_ids = sqlx::query_scalar!(
-            "SELECT id FROM file_info WHERE file_status = 1",
-        ).fetch_all(&self.pool)
+    // 按客户端证书标识查找用户, 多个标识命中时取第一个
+    pub async fn get_user_by_cert_subjects(&self, subjects: &[String]) -> Result<Option<String>, sqlx::Error> {
+        if subjects.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = QueryBuilder::<MySql>::new("SELECT user_name FROM user WHERE cert_subject IN (");
+        let mut separated = builder.separated(", ");
+        for subject in subjects {
+            separated.push_bind(subject.clone());
+        }
+        builder.push(") LIMIT 1");
+
+        builder
+            .build_query_scalar::<String>()
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    pub async fn set_cert_subject(&self, user_name: &str, cert_subject: Option<&str>) -> Result<bool, sqlx::Error> {
+        let rst = sqlx::query!(
+            "UPDATE user SET cert_subject = ? WHERE user_name = ?",
+            cert_subject,
+            user_name,
+        ).execute(&self.pool)
         .await?;
-        Ok(file_ids)
+        Ok(rst.rows_affected() > 0)
+    }
+
+    pub async fn list_files(&self, query: &FileQuery) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let mut builder = QueryBuilder::<MySql>::new("SELECT * FROM file_info WHERE file_status = 1");
+
+        for pattern in &query.name_patterns {
+            builder.push(" AND file_name LIKE ").push_bind(pattern.clone());
+        }
+        if let Some(min_size) = query.min_size {
+            builder.push(" AND file_size >= ").push_bind(min_size);
+        }
+        if let Some(max_size) = query.max_size {
+            builder.push(" AND file_size <= ").push_bind(max_size);
+        }
+        if let Some(created_after) = query.created_after {
+            builder.push(" AND created_at >= ").push_bind(created_after);
+        }
+        if let Some(created_before) = query.created_before {
+            builder.push(" AND created_at < ").push_bind(created_before);
+        }
+
+        let column = query.sort.column();
+        let (cmp, order) = if query.desc { ("<", "DESC") } else { (">", "ASC") };
+
+        // keyset 分页: (排序列, id) 严格位于上一页最后一条之后
+        if let Some((value, id)) = &query.after {
+            builder.push(format!(" AND ({column} {cmp} "));
+            push_sort_value(&mut builder, value);
+            builder.push(format!(" OR ({column} = "));
+            push_sort_value(&mut builder, value);
+            builder.push(format!(" AND id {cmp} ")).push_bind(*id).push("))");
+        }
+
+        builder.push(format!(" ORDER BY {column} {order}, id {order} LIMIT "));
+        builder.push_bind(query.limit);
+
+        builder.build_query_as::<FileInfo>().fetch_all(&self.pool).await
+    }
+
+    // 文件名命中的权重高于描述和标签值
+    pub async fn search_files(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, sqlx::Error> {
+        let mut builder = QueryBuilder::<MySql>::new("SELECT f.*, ");
+        match &query.text {
+            Some(text) => {
+                builder.push("MATCH(f.file_name) AGAINST (").push_bind(text.clone());
+                builder.push(") * 2 + MATCH(f.description) AGAINST (").push_bind(text.clone());
+                builder.push(") AS score");
+            }
+            None => {
+                builder.push("CAST(0 AS DOUBLE) AS score");
+            }
+        }
+        builder.push(" FROM file_info f WHERE f.file_status = 1");
+
+        if let Some(text) = &query.text {
+            builder.push(" AND (MATCH(f.file_name) AGAINST (").push_bind(text.clone());
+            builder.push(") OR MATCH(f.description) AGAINST (").push_bind(text.clone());
+            builder.push(") OR EXISTS (SELECT 1 FROM file_tag t WHERE t.file_id = f.id AND MATCH(t.tag_key, t.tag_value) AGAINST (");
+            builder.push_bind(text.clone()).push(")))");
+        }
+        // 标签过滤按键值精确匹配, 走 idx_tag_key_tag_value 而不是全文索引
+        for (tag_key, tag_value) in &query.tags {
+            builder.push(" AND f.id IN (SELECT t.file_id FROM file_tag t WHERE t.tag_key = ");
+            builder.push_bind(tag_key.clone());
+            builder.push(" AND t.tag_value = ").push_bind(tag_value.clone()).push(")");
+        }
+        if let Some(extension) = &query.extension_pattern {
+            builder.push(" AND f.file_name LIKE ").push_bind(extension.clone());
+        }
+        if let Some(owner) = &query.owner {
+            builder.push(" AND f.owner = ").push_bind(owner.clone());
+        }
+        if let Some(user_name) = &query.visible_to {
+            builder.push(" AND (f.owner = ").push_bind(user_name.clone());
+            builder.push(" OR f.owner IS NULL OR f.owner = '')");
+        }
+        if let Some(created_after) = query.created_after {
+            builder.push(" AND f.created_at >= ").push_bind(created_after);
+        }
+        if let Some(created_before) = query.created_before {
+            builder.push(" AND f.created_at < ").push_bind(created_before);
+        }
+
+        builder.push(" ORDER BY score DESC, f.created_at DESC, f.id DESC LIMIT ");
+        builder.push_bind(query.limit);
+
+        builder.build_query_as::<SearchHit>().fetch_all(&self.pool).await
     }
 
     pub async fn get_file_info_by_id(&self, id: i32) -> Result<FileInfo, sqlx::Error> {
@@ -118,13 +462,23 @@ impl SqlManipulator {
 
     pub async fn get_file_block_ids_by_file_id(&self, file_id: i32) -> Result<Vec<i32>, sqlx::Error> {
         let block_ids = sqlx::query_scalar!(
-            "SELECT id FROM file_block WHERE file_id = ?",
+            "SELECT id FROM file_block WHERE file_id = ? ORDER BY block_id",
             file_id,
         ).fetch_all(&self.pool)
         .await?;
         Ok(block_ids)
     }
 
+    pub async fn get_blocks_by_file_id(&self, file_id: i32) -> Result<Vec<FileBlock>, sqlx::Error> {
+        let blocks = sqlx::query_as!(
+            FileBlock,
+            "SELECT * FROM file_block WHERE file_id = ? ORDER BY block_id",
+            file_id,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(blocks)
+    }
+
     pub async fn get_block_info_by_id(&self, block_id: i32) -> Result<FileBlock, sqlx::Error> {
         let block_info = sqlx::query_as!(
             FileBlock,
@@ -134,29 +488,297 @@ impl SqlManipulator {
         .await?;
         Ok(block_info)
     }
+
+    pub async fn get_block_infos_after(&self, after_id: i32, limit: u32) -> Result<Vec<FileBlock>, sqlx::Error> {
+        let blocks = sqlx::query_as!(
+            FileBlock,
+            "SELECT * FROM file_block WHERE id > ? ORDER BY id LIMIT ?",
+            after_id,
+            limit,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(blocks)
+    }
+
+    pub async fn set_block_status(&self, id: i32, block_status: i32) -> Result<(), sqlx::Error> {
+        sqlx::query_scalar!(
+            "UPDATE file_block SET block_status = ?, checked_at = NOW() WHERE id = ?",
+            block_status,
+            id,
+        ).execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_directory(&self, parent_id: i32, dir_name: &str) -> Result<Option<Directory>, sqlx::Error> {
+        let dir = sqlx::query_as!(
+            Directory,
+            "SELECT * FROM directory WHERE parent_id = ? AND dir_name = ?",
+            parent_id,
+            dir_name,
+        ).fetch_optional(&self.pool)
+        .await?;
+        Ok(dir)
+    }
+
+    pub async fn directory_exists(&self, id: i32) -> Result<bool, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM directory WHERE id = ?",
+            id,
+        ).fetch_one(&self.pool)
+        .await?;
+        Ok(count > 0)
+    }
+
+    pub async fn get_directory_by_id(&self, id: i32) -> Result<Directory, sqlx::Error> {
+        let dir = sqlx::query_as!(
+            Directory,
+            "SELECT * FROM directory WHERE id = ?",
+            id,
+        ).fetch_one(&self.pool)
+        .await?;
+        Ok(dir)
+    }
+
+    pub async fn create_directory(&self, parent_id: i32, dir_name: &str) -> Result<i32, sqlx::Error> {
+        let rst = sqlx::query_scalar!(
+            "INSERT INTO directory (parent_id, dir_name) VALUES (?, ?)",
+            parent_id,
+            dir_name,
+        ).execute(&self.pool)
+        .await?;
+        Ok(rst.last_insert_id() as i32)
+    }
+
+    pub async fn get_sub_directories(&self, parent_id: i32) -> Result<Vec<Directory>, sqlx::Error> {
+        let dirs = sqlx::query_as!(
+            Directory,
+            "SELECT * FROM directory WHERE parent_id = ? ORDER BY dir_name",
+            parent_id,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(dirs)
+    }
+
+    pub async fn get_dir_files(&self, dir_id: i32) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let files = sqlx::query_as!(
+            FileInfo,
+            "SELECT * FROM file_info WHERE dir_id = ? AND file_status = 1 ORDER BY file_name",
+            dir_id,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(files)
+    }
+
+    // 目录中未删除的文件 (含未完成上传与历史版本) 的所有者
+    pub async fn get_dir_owners(&self, dir_id: i32) -> Result<Vec<Option<String>>, sqlx::Error> {
+        let owners = sqlx::query_scalar!(
+            "SELECT DISTINCT owner FROM file_info WHERE dir_id = ? AND file_status IN (0, 1, 3)",
+            dir_id,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(owners)
+    }
+
+    pub async fn get_dir_file(&self, dir_id: i32, file_name: &str) -> Result<Option<FileInfo>, sqlx::Error> {
+        let file = sqlx::query_as!(
+            FileInfo,
+            "SELECT * FROM file_info WHERE dir_id = ? AND file_name = ? AND file_status = 1 LIMIT 1",
+            dir_id,
+            file_name,
+        ).fetch_optional(&self.pool)
+        .await?;
+        Ok(file)
+    }
+
+    pub async fn move_directory(&self, id: i32, parent_id: i32, dir_name: &str) -> Result<(), sqlx::Error> {
+        sqlx::query_scalar!(
+            "UPDATE directory SET parent_id = ?, dir_name = ? WHERE id = ?",
+            parent_id,
+            dir_name,
+            id,
+        ).execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn move_file(&self, id: i32, dir_id: i32, file_name: &str) -> Result<(), sqlx::Error> {
+        sqlx::query_scalar!(
+            "UPDATE file_info SET dir_id = ?, file_name = ? WHERE id = ?",
+            dir_id,
+            file_name,
+            id,
+        ).execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // 删除目录树: 目录下的文件移入回收站, 目录记录直接移除
+    // 返回被删除的已完成文件, 供调用方发布事件
+    pub async fn delete_directories(&self, dir_ids: &[i32], deleted_by: &str) -> Result<Vec<FileInfo>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut deleted = Vec::new();
+        for dir_id in dir_ids {
+            let files = sqlx::query_as!(
+                FileInfo,
+                "SELECT * FROM file_info WHERE dir_id = ? AND file_status = 1 FOR UPDATE",
+                dir_id,
+            ).fetch_all(&mut *tx)
+            .await?;
+            deleted.extend(files);
+            sqlx::query_scalar!(
+                "UPDATE file_info SET file_status = 2, deleted_at = NOW(), deleted_by = ? WHERE dir_id = ? AND file_status IN (0, 1)",
+                deleted_by,
+                dir_id,
+            ).execute(&mut *tx)
+            .await?;
+            // 历史版本随当前版本保留, 移到根目录, 之后恢复版本时不会指向已删除的目录
+            sqlx::query_scalar!(
+                "UPDATE file_info SET dir_id = 0 WHERE dir_id = ? AND file_status = 3",
+                dir_id,
+            ).execute(&mut *tx)
+            .await?;
+            sqlx::query_scalar!(
+                "DELETE FROM directory WHERE id = ?",
+                dir_id,
+            ).execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    pub async fn get_file_tags(&self, file_id: i32) -> Result<Vec<(String, String)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT tag_key, tag_value FROM file_tag WHERE file_id = ? ORDER BY tag_key",
+            file_id,
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|row| (row.tag_key, row.tag_value)).collect())
+    }
+
+    pub async fn update_file_meta(
+        &self,
+        file_id: i32,
+        mime_type: Option<&str>,
+        description: Option<&str>,
+        set_tags: &[(String, String)],
+        remove_tags: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query_scalar!(
+            "UPDATE file_info SET mime_type = ?, description = ? WHERE id = ?",
+            mime_type,
+            description,
+            file_id,
+        ).execute(&mut *tx)
+        .await?;
+        for tag_key in remove_tags {
+            sqlx::query_scalar!(
+                "DELETE FROM file_tag WHERE file_id = ? AND tag_key = ?",
+                file_id,
+                tag_key,
+            ).execute(&mut *tx)
+            .await?;
+        }
+        for (tag_key, tag_value) in set_tags {
+            sqlx::query_scalar!(
+                "INSERT INTO file_tag (file_id, tag_key, tag_value) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE tag_value = VALUES(tag_value)",
+                file_id,
+                tag_key,
+                tag_value,
+            ).execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_wrapped_data_keys(&self) -> Result<Vec<(i32, Vec<u8>)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT id, data_key FROM file_info WHERE data_key IS NOT NULL",
+        ).fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().filter_map(|row| Some((row.id, row.data_key?))).collect())
+    }
+
+    pub async fn update_wrapped_data_keys(&self, keys: Vec<(i32, Vec<u8>)>) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for (file_id, data_key) in keys {
+            sqlx::query_scalar!(
+                "UPDATE file_info SET data_key = ? WHERE id = ?",
+                data_key,
+                file_id,
+            ).execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
 }
 
 #[derive(sqlx::FromRow, Serialize, Deserialize)]
 pub struct FileBlock {
-    id: i32,
-    file_id: i32,
+    pub id: i32,
+    pub file_id: i32,
     pub block_name: String,
-    block_id: i64,
-    block_checksum: u32,
-    block_size: u32,
+    pub block_id: i64,
+    pub block_checksum: u32,
+    pub block_size: u32,
     created_at: NaiveDateTime,
+    pub block_status: i32,
+    checked_at: Option<NaiveDateTime>,
 }
 
 #[derive(sqlx::FromRow, Serialize, Deserialize, Debug)]
 pub struct FileInfo {
-    id: i32,
+    pub id: i32,
     pub file_name: String,
-    file_size: i64,
-    file_checksum: u32,
+    pub file_size: i64,
+    pub file_checksum: u32,
     pub file_status: i32,
+    pub created_at: NaiveDateTime,
+    #[serde(skip)]
+    pub data_key: Option<Vec<u8>>,
+    pub dir_id: i32,
+    pub owner: Option<String>,
+    pub mime_type: Option<String>,
+    pub description: Option<String>,
+    pub logical_id: i32,
+    pub version: i32,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub deleted_by: Option<String>,
+    pub stored_size: Option<i64>,
+}
+
+impl FileInfo {
+    // 当前版本和历史版本都可以下载
+    pub fn is_readable(&self) -> bool {
+        matches!(self.file_status, FILE_COMPLETED | FILE_SUPERSEDED)
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        matches!(self.file_status, FILE_UPLOADING | FILE_COMPLETED)
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
+pub struct Directory {
+    pub id: i32,
+    pub parent_id: i32,
+    pub dir_name: String,
     created_at: NaiveDateTime,
 }
 
+#[derive(sqlx::FromRow, Serialize, Deserialize, Default)]
+pub struct UserQuota {
+    pub user_name: String,
+    pub max_bytes: Option<i64>,
+    pub max_files: Option<i32>,
+    pub used_bytes: i64,
+    pub file_count: i32,
+}
+
 #[derive(sqlx::FromRow, Serialize, Deserialize)]
 pub struct User {
     id: i32,
@@ -165,6 +787,93 @@ pub struct User {
     created_at: NaiveDateTime,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Name,
+    Size,
+    #[default]
+    CreatedAt,
+}
+
+impl SortKey {
+    fn column(&self) -> &'static str {
+        match self {
+            SortKey::Name => "file_name",
+            SortKey::Size => "file_size",
+            SortKey::CreatedAt => "created_at",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortValue {
+    Name(String),
+    Size(i64),
+    CreatedAt(NaiveDateTime),
+}
+
+impl SortValue {
+    pub fn of(sort: SortKey, file_info: &FileInfo) -> Self {
+        match sort {
+            SortKey::Name => SortValue::Name(file_info.file_name.clone()),
+            SortKey::Size => SortValue::Size(file_info.file_size),
+            SortKey::CreatedAt => SortValue::CreatedAt(file_info.created_at),
+        }
+    }
+
+    pub fn key(&self) -> SortKey {
+        match self {
+            SortValue::Name(_) => SortKey::Name,
+            SortValue::Size(_) => SortKey::Size,
+            SortValue::CreatedAt(_) => SortKey::CreatedAt,
+        }
+    }
+}
+
+fn push_sort_value(builder: &mut QueryBuilder<'_, MySql>, value: &SortValue) {
+    match value {
+        SortValue::Name(name) => builder.push_bind(name.clone()),
+        SortValue::Size(size) => builder.push_bind(*size),
+        SortValue::CreatedAt(created_at) => builder.push_bind(*created_at),
+    };
+}
+
+pub struct FileQuery {
+    // 已转义的 LIKE 模式
+    pub name_patterns: Vec<String>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub created_after: Option<NaiveDateTime>,
+    pub created_before: Option<NaiveDateTime>,
+    pub sort: SortKey,
+    pub desc: bool,
+    pub after: Option<(SortValue, i32)>,
+    pub limit: u32,
+}
+
+pub struct SearchQuery {
+    pub text: Option<String>,
+    pub tags: Vec<(String, String)>,
+    // 已转义的 LIKE 模式, 如 %.pdf
+    pub extension_pattern: Option<String>,
+    pub owner: Option<String>,
+    // 非管理员只能搜到自己的文件和没有所有者的旧文件, None 表示不限制
+    pub visible_to: Option<String>,
+    pub created_after: Option<NaiveDateTime>,
+    pub created_before: Option<NaiveDateTime>,
+    pub limit: u32,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub file_info: FileInfo,
+    pub score: f64,
+}
+
 static DATA: OnceCell<SqlManipulator> = OnceCell::const_new();
 
 pub async fn get_sql_opt() -> &'static SqlManipulator {
@@ -0,0 +1,585 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{Engine as _, engine::general_purpose};
+use dashmap::DashMap;
+use log::*;
+use openssl::{
+    nid::Nid,
+    ssl::{Ssl, SslAcceptor, SslRef},
+    x509::X509Ref,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore, mpsc},
+    task::JoinSet,
+};
+use tokio_openssl::SslStream;
+
+use crate::{
+    engine::{
+        context::{self, RequestContext},
+        engine::CertAuthenticator,
+        http::Route,
+        limiter::{ConnectionGuard, ConnectionLimiter, RateLimiter},
+        proxy::{self, Cidr},
+        return_code::{ErrorCode, Handler, ReturnCode},
+        transport::{self, FrameError, FrameReader, FrameWriter, IO_TIMEOUT, Incoming, Outgoing, Socket},
+    },
+    make_failed_resp,
+    utils::END_MARK,
+};
+
+// 单个连接上同时执行的带标识请求上限
+const MAX_INFLIGHT: usize = 64;
+const MAX_REQUEST_ID_LEN: usize = 64;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// WebSocket 定期发送 ping, 浏览器回复的 pong 会刷新空闲计时
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+// 监听器上使用的应用层协议
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Native,
+    Http,
+}
+
+// 所有监听器共享的连接处理状态
+pub struct ConnState {
+    pub register: Arc<DashMap<String, Handler>>,
+    pub max_frame_size: usize,
+    pub method_frame_limits: HashMap<String, usize>,
+    pub request_limiter: Option<Arc<RateLimiter>>,
+    pub method_limiters: HashMap<String, Arc<RateLimiter>>,
+    pub connection_limiter: Arc<ConnectionLimiter>,
+    pub method_concurrency: HashMap<String, Arc<Semaphore>>,
+    pub queue_timeout: Duration,
+    pub cert_authenticator: Option<CertAuthenticator>,
+    pub trusted_proxies: Vec<Cidr>,
+    pub routes: Vec<Route>,
+    pub ws_path: String,
+    pub ws_allowed_origins: Vec<String>,
+}
+
+impl ConnState {
+    // acceptor 为空时不做 TLS 握手, 用于本地 UDS
+    pub async fn handle_connection<S: Socket + fmt::Debug>(
+        self: &Arc<Self>,
+        mut stream: S,
+        peer_ip: String,
+        acceptor: Option<Arc<SslAcceptor>>,
+        protocol: Protocol,
+    ) {
+        // 来自可信代理的连接先读取 PROXY 头部, 之后的日志与限流都使用真实地址
+        let peer_ip = if proxy::is_trusted(&self.trusted_proxies, &peer_ip) {
+            let header = tokio::time::timeout(IO_TIMEOUT, proxy::read_header(&mut stream))
+                .await
+                .unwrap_or_else(|_| Err("read PROXY header timed out".to_string()));
+            match header {
+                Ok(Some(addr)) => {
+                    debug!("connection from proxy {} for {}", peer_ip, addr);
+                    addr.ip().to_canonical().to_string()
+                }
+                Ok(None) => peer_ip,
+                Err(e) => {
+                    warn!("invalid PROXY header from {}: {}", peer_ip, e);
+                    return;
+                }
+            }
+        } else {
+            peer_ip
+        };
+        let connection_guard = self.connection_limiter.try_acquire(&peer_ip);
+
+        // 超出上限时不做 TLS 握手直接关闭, 握手本身就是限流要避免的开销
+        if connection_guard.is_none() && acceptor.is_some() {
+            warn!("too many connections, drop {} before handshake", peer_ip);
+            return;
+        }
+
+        let Some(acceptor) = acceptor else {
+            let context = RequestContext {
+                peer_ip,
+                cert_user: None,
+            };
+            self.serve_protocol(protocol, stream, connection_guard, context).await;
+            return;
+        };
+
+        debug!("Starting SSL handshake");
+        let mut ssl_stream = match Ssl::new(acceptor.context()).and_then(|ssl| SslStream::new(ssl, stream)) {
+            Ok(ssl_stream) => ssl_stream,
+            Err(e) => {
+                warn!("SSL shakehand failed {}", e);
+                return;
+            }
+        };
+        match tokio::time::timeout(IO_TIMEOUT, Pin::new(&mut ssl_stream).accept()).await {
+            Ok(Ok(())) => {
+                debug!("SSL handshake success");
+                let cert_user = self.cert_user(ssl_stream.ssl(), &peer_ip).await;
+                let context = RequestContext { peer_ip, cert_user };
+                self.serve_protocol(protocol, ssl_stream, connection_guard, context).await;
+            }
+            Ok(Err(e)) => {
+                warn!("SSL shakehand failed {}", e)
+            }
+            Err(_) => {
+                warn!("SSL handshake with {} timed out", peer_ip)
+            }
+        }
+    }
+
+    async fn serve_protocol<S: Socket>(
+        self: &Arc<Self>,
+        protocol: Protocol,
+        stream: S,
+        connection_guard: Option<ConnectionGuard>,
+        context: RequestContext,
+    ) {
+        match protocol {
+            Protocol::Native => self.serve(stream, connection_guard, context).await,
+            Protocol::Http => self.serve_http(stream, connection_guard, context).await,
+        }
+    }
+
+    // 握手时已由 OpenSSL 校验证书链, 这里只做身份映射
+    async fn cert_user(&self, ssl: &SslRef, peer_ip: &str) -> Option<String> {
+        let authenticator = self.cert_authenticator.as_ref()?;
+        let cert = ssl.peer_certificate()?;
+        let identities = cert_identities(&cert);
+        let cert_user = authenticator(identities.clone()).await;
+        match &cert_user {
+            Some(user) => debug!("client cert of {} mapped to user {}", peer_ip, user),
+            None => warn!("client cert {:?} of {} matches no user", identities, peer_ip),
+        }
+        cert_user
+    }
+
+    async fn serve<S: Socket>(
+        self: &Arc<Self>,
+        stream: S,
+        connection_guard: Option<ConnectionGuard>,
+        context: RequestContext,
+    ) {
+        let (reader, mut writer) = transport::native(stream);
+
+        // 超出连接数上限时直接告知客户端后关闭
+        let Some(_connection_guard) = connection_guard else {
+            warn!("too many connections, reject {}", context.peer_ip);
+            let result = make_failed_resp!(code: ErrorCode::ServerBusy, payload: "too many connections");
+            if let Err(e) = writer.write(Outgoing::Frame(encode_response(&result))).await {
+                warn!("Failed to send msg: {}", e);
+            }
+            return;
+        };
+
+        self.serve_frames(reader, writer, context, false).await;
+    }
+
+    // 请求帧可带 "#<id> " 前缀, 带标识的请求并发执行, 响应 (含流式响应帧) 带同一前缀按完成顺序写回
+    // persistent 为 false 时 (原生协议) 未带标识的首个请求处理完即关闭, 收到带标识的请求后连接转为持久
+    // 读写两端在同一任务中并发运行, 等待数据时不占用运行时线程
+    pub async fn serve_frames<R: FrameReader, W: FrameWriter>(
+        self: &Arc<Self>,
+        reader: R,
+        writer: W,
+        context: RequestContext,
+        persistent: bool,
+    ) {
+        let (sender, receiver) = mpsc::channel::<Outgoing>(MAX_INFLIGHT);
+        let writing = write_frames(writer, receiver);
+        let reading = self.read_requests(reader, sender, context, persistent);
+        tokio::pin!(writing, reading);
+
+        tokio::select! {
+            // 写出失败时连接已不可用, 放弃进行中的请求
+            _ = &mut writing => {}
+            // 读端结束后继续写回进行中请求的响应, 直到所有发送端释放
+            _ = &mut reading => writing.await,
+        }
+    }
+
+    async fn read_requests<R: FrameReader>(
+        self: &Arc<Self>,
+        mut reader: R,
+        sender: mpsc::Sender<Outgoing>,
+        context: RequestContext,
+        mut persistent: bool,
+    ) {
+        let mut inflight = JoinSet::new();
+        let state = Arc::clone(self);
+        let limit = move |buffer: &[u8]| state.frame_limit(buffer);
+
+        loop {
+            while inflight.try_join_next().is_some() {}
+
+            // read_frame 可安全取消, 超时后未读完的帧保留到下次读取
+            let frame = match tokio::time::timeout(IDLE_TIMEOUT, reader.read_frame(&limit)).await {
+                Ok(Ok(Incoming::Request(frame))) => frame,
+                Ok(Ok(Incoming::Reply(message))) => {
+                    if sender.send(message).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Ok(Ok(Incoming::KeepAlive)) => continue,
+                Err(_) if inflight.is_empty() => {
+                    debug!("connection from {} idle, closed", context.peer_ip);
+                    let _ = sender.send(Outgoing::Close(1000)).await;
+                    return;
+                }
+                Err(_) => continue,
+                Ok(Err(FrameError::Closed(code))) => {
+                    if let Some(code) = code {
+                        let _ = sender.send(Outgoing::Close(code)).await;
+                    }
+                    break;
+                }
+                Ok(Err(FrameError::TooLarge(limit))) => {
+                    warn!("request frame exceeds {} bytes, rejected", limit);
+                    let result = make_failed_resp!(
+                        code: ErrorCode::FrameTooLarge,
+                        payload: format!("request frame exceeds {limit} bytes")
+                    );
+                    let _ = sender.send(Outgoing::Frame(encode_response(&result))).await;
+                    break;
+                }
+            };
+
+            if frame.is_empty() {
+                if persistent {
+                    continue;
+                }
+                return;
+            }
+
+            debug!("handle recv data");
+            let request = String::from_utf8_lossy(&frame).to_string();
+            let (id, request) = match split_request_id(request) {
+                Ok(split) => split,
+                Err(msg) => {
+                    let result = make_failed_resp!(payload: msg);
+                    if sender.send(Outgoing::Frame(encode_response(&result))).await.is_err() {
+                        return;
+                    }
+                    if persistent {
+                        continue;
+                    }
+                    return;
+                }
+            };
+
+            if let Some(id) = id {
+                persistent = true;
+                if inflight.len() >= MAX_INFLIGHT {
+                    warn!("too many requests in flight from {}", context.peer_ip);
+                    let result = make_failed_resp!(code: ErrorCode::ServerBusy, payload: "too many requests in flight");
+                    if sender.send(Outgoing::Frame(encode_tagged_response(&id, &result))).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+
+                let state = Arc::clone(self);
+                let sender = sender.clone();
+                let context = context.clone();
+                inflight.spawn(async move {
+                    let (result, _permit) = state
+                        .dispatch(request, context)
+                        .await
+                        .unwrap_or_else(|| (make_failed_resp!(payload: "method not found"), None));
+                    if sender.send(Outgoing::Frame(encode_tagged_response(&id, &result))).await.is_err() {
+                        return;
+                    }
+                    if let Some(mut frames) = result.stream {
+                        while let Some(frame) = frames.recv().await {
+                            trace!("Stream resp: {:?}", frame);
+                            if sender.send(Outgoing::Frame(encode_tagged_response(&id, &frame))).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+                continue;
+            }
+
+            // 未带标识的请求按原方式依次处理
+            let (result, _permit) = match self.dispatch(request, context.clone()).await {
+                Some(dispatched) => dispatched,
+                None if persistent => (make_failed_resp!(payload: "method not found"), None),
+                None => return,
+            };
+
+            if sender.send(Outgoing::Frame(encode_response(&result))).await.is_err() {
+                return;
+            }
+            // 长连接上的流式响应放入 inflight 转发, 避免阻塞读循环
+            if persistent && let Some(mut frames) = result.stream {
+                let sender = sender.clone();
+                inflight.spawn(async move {
+                    let _permit = _permit;
+                    while let Some(frame) = frames.recv().await {
+                        trace!("Stream resp: {:?}", frame);
+                        if sender.send(Outgoing::Frame(encode_response(&frame))).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+                continue;
+            }
+            if let Some(mut frames) = result.stream {
+                while let Some(frame) = frames.recv().await {
+                    trace!("Stream resp: {:?}", frame);
+                    if sender.send(Outgoing::Frame(encode_response(&frame))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            if !persistent {
+                return;
+            }
+        }
+
+        // 对端不再发送请求, 等待进行中的请求完成, 其响应由写端写回
+        while inflight.join_next().await.is_some() {}
+    }
+
+    // 执行限流与并发控制后调用 handler, 方法未注册时返回 None
+    // 返回的许可需持有到响应 (包括流式响应) 写完
+    pub async fn dispatch(
+        &self,
+        request: String,
+        context: RequestContext,
+    ) -> Option<(ReturnCode, Option<OwnedSemaphorePermit>)> {
+        let method = request.split(' ').next().unwrap_or_default().to_string();
+        let handler = self.register.get(&method)?;
+        debug!("enter handler {}", method);
+
+        let peer_ip = &context.peer_ip;
+        let limited = self.request_limiter.as_ref().is_some_and(|limiter| !limiter.try_acquire(peer_ip))
+            || self.method_limiters.get(&method).is_some_and(|limiter| !limiter.try_acquire(peer_ip));
+        if limited {
+            warn!("request {} from {} rate limited", method, peer_ip);
+            return Some((make_failed_resp!(code: ErrorCode::RateLimited, payload: "too many requests"), None));
+        }
+
+        let permit = match self.method_concurrency.get(&method) {
+            Some(semaphore) => {
+                match tokio::time::timeout(self.queue_timeout, Arc::clone(semaphore).acquire_owned()).await {
+                    Ok(Ok(permit)) => Some(permit),
+                    _ => {
+                        warn!("request {} from {} timed out in queue", method, peer_ip);
+                        return Some((make_failed_resp!(code: ErrorCode::ServerBusy, payload: "server busy"), None));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let result = context::scope(context, handler.call(request)).await;
+        trace!("Resp: {:?}", result);
+        Some((result, permit))
+    }
+
+    fn frame_limit(&self, buffer: &[u8]) -> usize {
+        // 跳过请求标识, 按其后的方法名取上限
+        let buffer = match buffer.strip_prefix(b"#") {
+            Some(rest) => match rest.iter().position(|b| *b == b' ') {
+                Some(end) => &rest[end + 1..],
+                None => return self.max_frame_size,
+            },
+            None => buffer,
+        };
+        let Some(end) = buffer.iter().position(|b| *b == b' ') else {
+            return self.max_frame_size;
+        };
+        let method = String::from_utf8_lossy(&buffer[..end]);
+        self.method_frame_limits
+            .get(method.as_ref())
+            .copied()
+            .unwrap_or(self.max_frame_size)
+    }
+}
+
+pub fn encode_response(result: &ReturnCode) -> String {
+    format!(
+        "{} {} {}\n{}",
+        result.success,
+        if let Some(control_block) = &result.control_block {
+            let control_block = serde_json::to_string(control_block).unwrap();
+            general_purpose::STANDARD.encode(&control_block)
+        } else {
+            ".".to_string()
+        },
+        if let Some(payload) = &result.payload {
+            general_purpose::STANDARD.encode(payload)
+        } else {
+            "".to_string()
+        },
+        END_MARK
+    )
+}
+
+pub fn encode_tagged_response(id: &str, result: &ReturnCode) -> String {
+    format!("#{} {}", id, encode_response(result))
+}
+
+// 依次写出响应帧并定期发送 ping (原生协议忽略), 发送端全部释放或写出关闭帧后结束
+async fn write_frames<W: FrameWriter>(mut writer: W, mut receiver: mpsc::Receiver<Outgoing>) {
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+    loop {
+        let message = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(message) => message,
+                None => return,
+            },
+            _ = ping.tick() => Outgoing::Ping,
+        };
+        let close = matches!(message, Outgoing::Close(_));
+        if let Err(e) = writer.write(message).await {
+            warn!("Failed to send msg: {}", e);
+            return;
+        }
+        if close {
+            return;
+        }
+    }
+}
+
+// 拆出请求帧的 "#<id> " 前缀
+fn split_request_id(request: String) -> Result<(Option<String>, String), String> {
+    let Some(rest) = request.strip_prefix('#') else {
+        return Ok((None, request));
+    };
+    let Some((id, request)) = rest.split_once(' ') else {
+        return Err("missing request after request id".to_string());
+    };
+    if id.is_empty() || id.len() > MAX_REQUEST_ID_LEN {
+        return Err(format!("request id must be 1 to {MAX_REQUEST_ID_LEN} bytes"));
+    }
+    Ok((Some(id.to_string()), request.to_string()))
+}
+
+// 客户端证书中可用于映射用户的标识: subject CN 以及 SAN 中的 email/DNS/URI
+fn cert_identities(cert: &X509Ref) -> Vec<String> {
+    let mut identities = Vec::new();
+    for entry in cert.subject_name().entries_by_nid(Nid::COMMONNAME) {
+        if let Ok(cn) = entry.data().as_utf8() {
+            identities.push(cn.to_string());
+        }
+    }
+    if let Some(names) = cert.subject_alt_names() {
+        for name in names.iter() {
+            if let Some(id) = name.email().or(name.dnsname()).or(name.uri()) {
+                identities.push(id.to_string());
+            }
+        }
+    }
+    identities
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{engine::return_code::into_handler, make_success_resp};
+
+    // 依次返回给定的帧, 之后一直挂起, 模拟保持连接的客户端
+    struct MockReader(VecDeque<&'static str>);
+
+    #[async_trait]
+    impl FrameReader for MockReader {
+        async fn read_frame(&mut self, _limit: &transport::FrameLimit) -> Result<Incoming, FrameError> {
+            match self.0.pop_front() {
+                Some(frame) => Ok(Incoming::Request(frame.as_bytes().to_vec())),
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn test_state() -> Arc<ConnState> {
+        let register = DashMap::new();
+        register.insert(
+            "subscribe".to_string(),
+            into_handler(|_| async {
+                // 流一直不结束
+                let (tx, rx) = mpsc::channel(1);
+                std::mem::forget(tx);
+                make_success_resp!(payload: "subscribed", stream: rx)
+            }),
+        );
+        register.insert("echo".to_string(), into_handler(|input| async move { make_success_resp!(payload: input) }));
+        Arc::new(ConnState {
+            register: Arc::new(register),
+            max_frame_size: 1024,
+            method_frame_limits: HashMap::new(),
+            request_limiter: None,
+            method_limiters: HashMap::new(),
+            connection_limiter: Arc::new(ConnectionLimiter::new(1, 1)),
+            method_concurrency: HashMap::new(),
+            queue_timeout: Duration::from_secs(1),
+            cert_authenticator: None,
+            trusted_proxies: Vec::new(),
+            routes: Vec::new(),
+            ws_path: String::new(),
+            ws_allowed_origins: Vec::new(),
+        })
+    }
+
+    async fn next_frame(rx: &mut mpsc::Receiver<Outgoing>) -> String {
+        let Ok(Some(Outgoing::Frame(frame))) = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await else {
+            panic!("expected a response frame");
+        };
+        frame
+    }
+
+    #[tokio::test]
+    async fn untagged_stream_does_not_block_persistent_connection() {
+        let state = test_state();
+        let reader = MockReader(VecDeque::from(["subscribe {}", "echo hello"]));
+        let (tx, mut rx) = mpsc::channel(8);
+        let task = tokio::spawn(async move {
+            state.read_requests(reader, tx, RequestContext::default(), true).await;
+        });
+
+        assert_eq!(next_frame(&mut rx).await, encode_response(&make_success_resp!(payload: "subscribed")));
+        // 订阅流未结束时仍能处理后续请求
+        assert_eq!(next_frame(&mut rx).await, encode_response(&make_success_resp!(payload: "echo hello")));
+        task.abort();
+    }
+
+    #[test]
+    fn untagged_request_passes_through() {
+        let (id, request) = split_request_id("login {\"a\":1}".to_string()).unwrap();
+        assert_eq!(id, None);
+        assert_eq!(request, "login {\"a\":1}");
+    }
+
+    #[test]
+    fn tagged_request_is_split() {
+        let (id, request) = split_request_id("#42 list_file {}".to_string()).unwrap();
+        assert_eq!(id.as_deref(), Some("42"));
+        assert_eq!(request, "list_file {}");
+    }
+
+    #[test]
+    fn rejects_missing_request_and_bad_ids() {
+        assert!(split_request_id("#42".to_string()).is_err());
+        assert!(split_request_id("# list_file {}".to_string()).is_err());
+
+        let long = format!("#{} list_file {{}}", "a".repeat(MAX_REQUEST_ID_LEN + 1));
+        assert!(split_request_id(long).is_err());
+        let max = format!("#{} list_file {{}}", "a".repeat(MAX_REQUEST_ID_LEN));
+        assert!(split_request_id(max).is_ok());
+    }
+}
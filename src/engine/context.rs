@@ -0,0 +1,21 @@
+use std::future::Future;
+
+// 当前请求所在连接的信息, 在 handler 内通过 current() 读取
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub peer_ip: String,
+    // 客户端证书映射到的用户名
+    pub cert_user: Option<String>,
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+pub async fn scope<F: Future>(context: RequestContext, f: F) -> F::Output {
+    REQUEST_CONTEXT.scope(context, f).await
+}
+
+pub fn current() -> Option<RequestContext> {
+    REQUEST_CONTEXT.try_with(|context| context.clone()).ok()
+}
@@ -1,27 +1,93 @@
 use std::{
-    io::{Read, Write},
-    net::TcpListener,
-    sync::Arc,
+    collections::HashMap,
+    fmt::Debug,
+    io::ErrorKind,
+    net::TcpListener as StdTcpListener,
+    path::Path,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixListener as StdUnixListener,
+    },
 };
 
-use crate::{
-    engine::return_code::{into_handler, Handler, ReturnCode},
-    make_failed_resp, utils::END_MARK,
+use crate::engine::{
+    conn::{ConnState, Protocol},
+    http::Route,
+    limiter::{ConnectionLimiter, RateLimiter},
+    proxy::Cidr,
+    return_code::{into_handler, Handler, ReturnCode},
+    tls::{self, ClientAuth, SharedAcceptor, SniCert, TlsConfig},
+    transport::Socket,
 };
-use base64::{Engine as _, engine::general_purpose};
 use dashmap::DashMap;
 use env_logger::fmt::style::{self, RgbColor};
 use log::*;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use openssl::ssl::SslVersion;
 use std::time::Duration;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{net::TcpListener, sync::Semaphore};
+
+// 根据客户端证书的 CN/SAN 查找对应用户
+pub type CertAuthenticator =
+    Arc<dyn Fn(Vec<String>) -> Pin<Box<dyn Future<Output = Option<String>> + Send>> + Send + Sync>;
+
+// 监听地址, TCP 为 "ip:port" (IPv6 写作 "[::]:port"), UDS 可选择是否启用 TLS
+// Https 为 REST 网关, 与原生协议共用 handler
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(String),
+    Unix { path: String, tls: bool },
+    Https(String),
+}
+
+// "https://addr", "unix+tls:path", "unix:path", 其余按 TCP 地址处理
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("https://") {
+            Ok(Listen::Https(addr.to_string()))
+        } else if let Some(path) = s.strip_prefix("unix+tls:") {
+            Ok(Listen::Unix { path: path.to_string(), tls: true })
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Listen::Unix { path: path.to_string(), tls: false })
+        } else {
+            Ok(Listen::Tcp(s.to_string()))
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Engine {
     register: Arc<DashMap<String, Handler>>,
-    private_key_file: String,
-    cert_file: String,
+    tls: TlsConfig,
+    dev_cert_sans: Option<Vec<String>>,
+    cert_reload_interval: Duration,
     addr: String,
     port: u16,
+    listeners: Vec<Listen>,
+    max_frame_size: usize,
+    method_frame_limits: HashMap<String, usize>,
+    request_limiter: Option<Arc<RateLimiter>>,
+    method_limiters: HashMap<String, Arc<RateLimiter>>,
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    method_concurrency: HashMap<String, Arc<Semaphore>>,
+    queue_timeout: Duration,
+    cert_authenticator: Option<CertAuthenticator>,
+    trusted_proxies: Vec<Cidr>,
+    routes: Vec<Route>,
+    ws_path: String,
+    ws_allowed_origins: Vec<String>,
+    unix_socket_mode: u32,
 }
 
 #[allow(unused)]
@@ -29,10 +95,30 @@ impl Engine {
     pub fn new() -> Self {
         Engine {
             register: Arc::new(DashMap::new()),
-            private_key_file: "private.key".to_string(),
-            cert_file: "certificate.crt".to_string(),
+            tls: TlsConfig {
+                private_key_file: "private.key".to_string(),
+                cert_file: "certificate.crt".to_string(),
+                ..Default::default()
+            },
+            dev_cert_sans: None,
+            cert_reload_interval: Duration::from_secs(60),
             addr: "127.0.0.1".to_string(),
             port: 7878,
+            listeners: Vec::new(),
+            max_frame_size: 64 * 1024 * 1024,
+            method_frame_limits: HashMap::new(),
+            request_limiter: None,
+            method_limiters: HashMap::new(),
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            method_concurrency: HashMap::new(),
+            queue_timeout: Duration::from_secs(5),
+            cert_authenticator: None,
+            trusted_proxies: Vec::new(),
+            routes: Vec::new(),
+            ws_path: "/ws".to_string(),
+            ws_allowed_origins: Vec::new(),
+            unix_socket_mode: 0o600,
         }
     }
 
@@ -47,12 +133,72 @@ impl Engine {
     }
 
     pub fn set_private_key_file(&mut self, file_path: &str) -> &mut Self {
-        self.private_key_file = file_path.to_string();
+        self.tls.private_key_file = file_path.to_string();
         self
     }
 
     pub fn set_cert_file(&mut self, file_path: &str) -> &mut Self {
-        self.cert_file = file_path.to_string();
+        self.tls.cert_file = file_path.to_string();
+        self
+    }
+
+    // 开发模式: 证书不存在时按给定 SAN 生成自签名证书
+    pub fn set_dev_cert(&mut self, sans: &[String]) -> &mut Self {
+        self.dev_cert_sans = Some(sans.to_vec());
+        self
+    }
+
+    pub fn set_tls_min_version(&mut self, version: SslVersion) -> &mut Self {
+        self.tls.min_version = Some(version);
+        self
+    }
+
+    // TLS 1.2 及以下的 cipher list, OpenSSL 格式
+    pub fn set_tls_ciphers(&mut self, cipher_list: &str) -> &mut Self {
+        self.tls.cipher_list = Some(cipher_list.to_string());
+        self
+    }
+
+    // TLS 1.3 的 ciphersuites, 如 "TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256"
+    pub fn set_tls_ciphersuites(&mut self, ciphersuites: &str) -> &mut Self {
+        self.tls.ciphersuites = Some(ciphersuites.to_string());
+        self
+    }
+
+    pub fn set_tls_groups(&mut self, groups: &str) -> &mut Self {
+        self.tls.groups = Some(groups.to_string());
+        self
+    }
+
+    pub fn set_session_tickets(&mut self, enabled: bool) -> &mut Self {
+        self.tls.session_tickets = enabled;
+        self
+    }
+
+    // 服务端会话缓存, 关闭后只能通过 session ticket 恢复会话
+    pub fn set_session_cache(&mut self, enabled: bool) -> &mut Self {
+        self.tls.session_cache = enabled;
+        self
+    }
+
+    // 按优先顺序列出支持的 ALPN 协议标识
+    pub fn set_alpn_protocols(&mut self, protocols: &[String]) -> &mut Self {
+        self.tls.alpn_protocols = protocols.to_vec();
+        self
+    }
+
+    pub fn add_sni_cert(&mut self, server_name: &str, private_key_file: &str, cert_file: &str) -> &mut Self {
+        self.tls.sni_certs.push(SniCert {
+            server_name: server_name.to_string(),
+            private_key_file: private_key_file.to_string(),
+            cert_file: cert_file.to_string(),
+        });
+        self
+    }
+
+    // 证书文件变化的检查间隔, 为 0 时只在收到 SIGHUP 时重载
+    pub fn set_cert_reload_interval(&mut self, interval: Duration) -> &mut Self {
+        self.cert_reload_interval = interval;
         self
     }
 
@@ -66,6 +212,130 @@ impl Engine {
         self
     }
 
+    pub fn add_listener(&mut self, addr: &str) -> &mut Self {
+        self.listeners.push(Listen::Tcp(addr.to_string()));
+        self
+    }
+
+    pub fn add_unix_listener(&mut self, path: &str, tls: bool) -> &mut Self {
+        self.listeners.push(Listen::Unix {
+            path: path.to_string(),
+            tls,
+        });
+        self
+    }
+
+    // UDS 文件的权限, 如 0o600 只允许运行服务的用户连接, 0o660 允许同组用户
+    pub fn set_unix_socket_mode(&mut self, mode: u32) -> &mut Self {
+        self.unix_socket_mode = mode;
+        self
+    }
+
+    pub fn add_http_listener(&mut self, addr: &str) -> &mut Self {
+        self.listeners.push(Listen::Https(addr.to_string()));
+        self
+    }
+
+    // 按添加顺序匹配, 第一个命中的路由生效
+    pub fn add_route(&mut self, route: Route) -> &mut Self {
+        self.routes.push(route);
+        self
+    }
+
+    // HTTPS 监听器上的 WebSocket 端点路径
+    pub fn set_ws_path(&mut self, path: &str) -> &mut Self {
+        self.ws_path = path.to_string();
+        self
+    }
+
+    // 允许发起 WebSocket 连接的 Origin, 为空时只接受与 Host 相同的来源
+    pub fn set_ws_allowed_origins(&mut self, origins: &[String]) -> &mut Self {
+        self.ws_allowed_origins = origins.to_vec();
+        self
+    }
+
+    // 只解析来自这些地址的 PROXY protocol 头部, 其他连接按直连处理
+    pub fn set_trusted_proxies(&mut self, trusted: &[Cidr]) -> &mut Self {
+        self.trusted_proxies = trusted.to_vec();
+        self
+    }
+
+    pub fn set_max_frame_size(&mut self, size: usize) -> &mut Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    // 单个方法的请求帧上限, 读到方法名后即生效
+    pub fn set_method_frame_limit(&mut self, method: &str, size: usize) -> &mut Self {
+        self.method_frame_limits.insert(method.to_string(), size);
+        self
+    }
+
+    // 按客户端 IP 限制所有请求的速率, per_sec 为 0 时不限制
+    pub fn set_request_rate(&mut self, per_sec: f64, burst: f64) -> &mut Self {
+        self.request_limiter = (per_sec > 0.0).then(|| Arc::new(RateLimiter::new(per_sec, burst)));
+        self
+    }
+
+    // 按客户端 IP 限制单个方法的速率
+    pub fn set_method_rate(&mut self, method: &str, per_sec: f64, burst: f64) -> &mut Self {
+        if per_sec > 0.0 {
+            self.method_limiters
+                .insert(method.to_string(), Arc::new(RateLimiter::new(per_sec, burst)));
+        } else {
+            self.method_limiters.remove(method);
+        }
+        self
+    }
+
+    // 同时处理的连接数上限, 0 为不限制
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Self {
+        self.max_connections = max;
+        self
+    }
+
+    pub fn set_max_connections_per_ip(&mut self, max: usize) -> &mut Self {
+        self.max_connections_per_ip = max;
+        self
+    }
+
+    // 单个方法同时执行的 handler 数量, 超出的请求排队等待
+    pub fn set_method_concurrency(&mut self, method: &str, max: usize) -> &mut Self {
+        if max > 0 {
+            self.method_concurrency
+                .insert(method.to_string(), Arc::new(Semaphore::new(max)));
+        } else {
+            self.method_concurrency.remove(method);
+        }
+        self
+    }
+
+    // 排队超过该时间仍未执行的请求返回 ERR_SERVER_BUSY
+    pub fn set_queue_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    // 用于校验客户端证书的 CA 文件, 与 set_client_auth 配合开启 mTLS
+    pub fn set_client_ca_file(&mut self, file_path: &str) -> &mut Self {
+        self.tls.client_ca_file = Some(file_path.to_string());
+        self
+    }
+
+    pub fn set_client_auth(&mut self, mode: ClientAuth) -> &mut Self {
+        self.tls.client_auth = mode;
+        self
+    }
+
+    pub fn set_cert_authenticator<F, Fut>(&mut self, func: F) -> &mut Self
+    where
+        F: Fn(Vec<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<String>> + Send + 'static,
+    {
+        self.cert_authenticator = Some(Arc::new(move |identities| Box::pin(func(identities))));
+        self
+    }
+
     async fn run_handler(&self, method: &str, arg: String) -> Option<ReturnCode> {
         if let Some(entry) = self.register.get(method) {
             let func = entry.value();
@@ -75,25 +345,75 @@ impl Engine {
         }
     }
 
-    fn build(&self) -> Result<(Arc<SslAcceptor>, TcpListener), Box<dyn std::error::Error>> {
-        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
-        builder.set_private_key_file(&self.private_key_file, SslFiletype::PEM)?;
-        builder.set_certificate_chain_file(&self.cert_file)?;
-        builder.check_private_key()?;
+    fn build_acceptor(&self) -> Result<SharedAcceptor, Box<dyn std::error::Error>> {
+        if let Some(sans) = &self.dev_cert_sans {
+            tls::ensure_dev_cert(&self.tls.private_key_file, &self.tls.cert_file, sans)
+                .map_err(|e| format!("generate dev certificate err: {e}"))?;
+        }
+        for file in [&self.tls.private_key_file, &self.tls.cert_file] {
+            if !Path::new(file).exists() {
+                return Err(format!("{file} not found, generate a certificate or set DEV_MODE=true").into());
+            }
+        }
 
-        let acceptor = builder.build();
-        let acceptor = Arc::new(acceptor);
+        let acceptor = self.tls.build_acceptor().map_err(|e| e as Box<dyn std::error::Error>)?;
+        Ok(Arc::new(RwLock::new(Arc::new(acceptor))))
+    }
 
-        let listener = TcpListener::bind(format!("{}:{}", &self.addr, &self.port))?;
-        listener.set_nonblocking(false)?;
+    // 未添加任何监听器时使用 addr:port
+    fn bind(&self) -> Result<Vec<(Listener, bool, Protocol)>, Box<dyn std::error::Error>> {
+        let default_listen = [Listen::Tcp(format!("{}:{}", self.addr, self.port))];
+        let listens = if self.listeners.is_empty() {
+            &default_listen[..]
+        } else {
+            &self.listeners[..]
+        };
 
         let style = style::Style::new()
             .bold()
             .fg_color(Some(style::Color::Rgb(RgbColor(0, 164, 164))));
 
-        info!("Listening at {style}{}:{}{style:#}", self.addr, self.port);
-
-        Ok((acceptor, listener))
+        let mut listeners = Vec::with_capacity(listens.len());
+        for listen in listens {
+            match listen {
+                Listen::Tcp(addr) => {
+                    let listener = StdTcpListener::bind(addr)
+                        .map_err(|e| format!("bind {addr} err: {e}"))?;
+                    listener.set_nonblocking(true)?;
+                    info!("Listening at {style}{}{style:#}", addr);
+                    listeners.push((Listener::Tcp(listener), true, Protocol::Native));
+                }
+                Listen::Https(addr) => {
+                    let listener = StdTcpListener::bind(addr)
+                        .map_err(|e| format!("bind {addr} err: {e}"))?;
+                    listener.set_nonblocking(true)?;
+                    info!("Listening at {style}https://{}{style:#}", addr);
+                    listeners.push((Listener::Tcp(listener), true, Protocol::Http));
+                }
+                #[cfg(unix)]
+                Listen::Unix { path, tls } => {
+                    // 上次退出残留的 socket 文件会导致 bind 失败; 只删除 socket, 不误删其他文件
+                    match std::fs::symlink_metadata(path) {
+                        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+                        Ok(_) => return Err(format!("bind {path} err: path exists and is not a socket").into()),
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) => return Err(format!("bind {path} err: {e}").into()),
+                    }
+                    let listener = StdUnixListener::bind(path)
+                        .map_err(|e| format!("bind {path} err: {e}"))?;
+                    listener.set_nonblocking(true)?;
+                    // 不依赖 umask, 访问权限即是本地 socket 的认证
+                    std::fs::set_permissions(path, Permissions::from_mode(self.unix_socket_mode))?;
+                    info!("Listening at {style}unix:{}{style:#}{}", path, if *tls { " (tls)" } else { "" });
+                    listeners.push((Listener::Unix(listener), *tls, Protocol::Native));
+                }
+                #[cfg(not(unix))]
+                Listen::Unix { path, .. } => {
+                    return Err(format!("unix socket {path} is not supported on this platform").into());
+                }
+            }
+        }
+        Ok(listeners)
     }
 
     fn log_engine_info(&self) {
@@ -122,95 +442,98 @@ impl Engine {
     pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.log_engine_info();
 
-        let (acceptor, listener) = self.build()?;
-
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    debug!("new connection established");
-
-                    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
-                    stream.set_write_timeout(Some(Duration::from_secs(30)))?;
-
-                    let acceptor_clone = Arc::clone(&acceptor);
-                    let register = Arc::clone(&self.register);
-
-                    tokio::spawn(async move {
-                        debug!("Starting SSL handshake");
-
-                        match acceptor_clone.accept(stream) {
-                            Ok(mut ssl_stream) => {
-                                debug!("SSL handshake success");
-
-                                let mut metadata_buffer = Vec::new();
-                                let mut temp_buffer = [0; 1024];
-                                loop {
-                                    let n = ssl_stream.read(&mut temp_buffer).unwrap_or_else(|e| {
-                                        warn!("Failed to read msg: {}", e);
-                                        0
-                                    });
-                                    if n == 0 {
-                                        break;
-                                    }
-                                    metadata_buffer.extend_from_slice(&temp_buffer[0..n]);
-                                    if metadata_buffer.ends_with(END_MARK.as_bytes()) {
-                                        metadata_buffer.truncate(metadata_buffer.len() - END_MARK.len());
-                                        break; 
-                                    }
-                                }
-
-                                if metadata_buffer.len() > 0 {
-                                    debug!("handle recv data");
-                                    let n = metadata_buffer.len();
-                                    let metadata_str =
-                                        String::from_utf8_lossy(&metadata_buffer[..n]);
-                                    let parts: Vec<&str> = metadata_str.split(' ').collect();
-                                    let method = parts[0];
-                                    if register.contains_key(method) {
-                                        debug!("enter handler {}", method);
-
-                                        let result = if let Some(handler) = register.get(method) {
-                                            handler.call(metadata_str.to_string()).await
-                                        } else {
-                                            make_failed_resp!(payload: "method not found")
-                                        };
-
-                                        trace!("Resp: {:?}", result);
-
-                                        let response = format!(
-                                            "{} {} {}\n",
-                                            result.success,
-                                            if let Some(control_block) = result.control_block {
-                                                let control_block =
-                                                    serde_json::to_string(&control_block).unwrap();
-                                                general_purpose::STANDARD.encode(&control_block)
-                                            } else {
-                                                ".".to_string()
-                                            },
-                                            if let Some(payload) = result.payload {
-                                                general_purpose::STANDARD.encode(payload)
-                                            } else {
-                                                "".to_string()
-                                            }
-                                        );
-
-                                        if let Err(e) = ssl_stream.write_all(format!("{}{}", response, END_MARK).as_bytes()) {
-                                            warn!("Failed to send msg: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                warn!("SSL shakehand failed {}", e)
-                            }
-                        }
-                    });
-                }
-                Err(e) => {
-                    error!("failed to establish TCP connection: {}", e);
-                }
-            }
+        let acceptor = self.build_acceptor()?;
+        let listeners = self.bind()?;
+        tokio::spawn(self.tls.clone().watch(Arc::clone(&acceptor), self.cert_reload_interval));
+
+        let state = Arc::new(ConnState {
+            register: Arc::clone(&self.register),
+            max_frame_size: self.max_frame_size,
+            method_frame_limits: self.method_frame_limits.clone(),
+            request_limiter: self.request_limiter.clone(),
+            method_limiters: self.method_limiters.clone(),
+            connection_limiter: Arc::new(ConnectionLimiter::new(
+                self.max_connections,
+                self.max_connections_per_ip,
+            )),
+            method_concurrency: self.method_concurrency.clone(),
+            queue_timeout: self.queue_timeout,
+            cert_authenticator: self.cert_authenticator.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            routes: self.routes.clone(),
+            ws_path: self.ws_path.clone(),
+            ws_allowed_origins: self.ws_allowed_origins.clone(),
+        });
+
+        // 每个监听器一个 accept 任务, 共享同一份 handler 注册表
+        let mut tasks = Vec::with_capacity(listeners.len());
+        for (listener, use_tls, protocol) in listeners {
+            let acceptor = use_tls.then(|| Arc::clone(&acceptor));
+            let state = Arc::clone(&state);
+            tasks.push(tokio::spawn(accept_loop(listener, acceptor, state, protocol)));
+        }
+        for task in tasks {
+            task.await??;
         }
         Ok(())
     }
 }
+
+enum Listener {
+    Tcp(StdTcpListener),
+    #[cfg(unix)]
+    Unix(StdUnixListener),
+}
+
+async fn accept_loop(
+    listener: Listener,
+    acceptor: Option<SharedAcceptor>,
+    state: Arc<ConnState>,
+    protocol: Protocol,
+) -> Result<(), std::io::Error> {
+    match listener {
+        Listener::Tcp(listener) => {
+            let listener = TcpListener::from_std(listener)?;
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(rst) => rst,
+                    Err(e) => {
+                        error!("failed to establish TCP connection: {}", e);
+                        continue;
+                    }
+                };
+                spawn_connection(&state, stream, addr.ip().to_string(), acceptor.as_ref(), protocol);
+            }
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            let listener = UnixListener::from_std(listener)?;
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("failed to establish unix connection: {}", e);
+                        continue;
+                    }
+                };
+                spawn_connection(&state, stream, "unix".to_string(), acceptor.as_ref(), protocol);
+            }
+        }
+    }
+}
+
+fn spawn_connection<S: Socket + Debug + Send + 'static>(
+    state: &Arc<ConnState>,
+    stream: S,
+    peer_ip: String,
+    acceptor: Option<&SharedAcceptor>,
+    protocol: Protocol,
+) {
+    debug!("new connection established from {}", peer_ip);
+
+    let acceptor = acceptor.map(|acceptor| Arc::clone(&acceptor.read().unwrap()));
+    let state = Arc::clone(state);
+    tokio::spawn(async move {
+        state.handle_connection(stream, peer_ip, acceptor, protocol).await;
+    });
+}
@@ -0,0 +1,809 @@
+use std::{fmt::Write as _, sync::Arc};
+
+use base64::{Engine as _, engine::general_purpose};
+use log::*;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    control_block::ControlBlock,
+    engine::{
+        conn::ConnState,
+        context::RequestContext,
+        limiter::ConnectionGuard,
+        return_code::{ErrorCode, ReturnCode},
+        transport::{self, Socket, read_timeout, write_timeout},
+    },
+    utils::checksum,
+};
+
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    Str,
+    Int,
+    Bool,
+}
+
+impl ParamType {
+    // 路径与 query 参数都是字符串, 只转换路由声明了类型的参数
+    fn convert(self, name: &str, value: String) -> Result<Value, HttpError> {
+        let invalid = || HttpError::Status(400, format!("invalid value for {name}: {value}"));
+        match self {
+            ParamType::Str => Ok(Value::String(value)),
+            ParamType::Int => value.parse::<i64>().map(Value::from).map_err(|_| invalid()),
+            ParamType::Bool => match value.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Param(String, ParamType),
+}
+
+// REST 路由到已注册 handler 的映射
+// 路径参数、query 参数与 JSON body 合并成 handler 的 JSON 请求
+#[derive(Debug, Clone)]
+pub struct Route {
+    http_method: String,
+    segments: Vec<Segment>,
+    handler: String,
+    // (字段名, 校验和字段名, body 上限): 请求 body 为原始字节, 填入该字段
+    raw_request: Option<(String, Option<String>, usize)>,
+    query_types: Vec<(String, ParamType)>,
+    // 从响应 JSON 中取出字节数组字段, 以二进制返回
+    raw_response: Option<String>,
+    // 流式响应中每帧取出该字段, 以 chunked 编码连续返回
+    raw_stream: Option<String>,
+    no_input: bool,
+}
+
+impl Route {
+    // path 形如 "/files/{file_id:int}/blocks/{block_id:int}", 未声明类型的参数为字符串
+    pub fn new(http_method: &str, path: &str, handler: &str) -> Self {
+        let segments = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(param) => {
+                    let (name, param_type) = match param.split_once(':') {
+                        Some((name, "int")) => (name, ParamType::Int),
+                        Some((name, "bool")) => (name, ParamType::Bool),
+                        Some((_, other)) => panic!("unknown param type {other} in {path}"),
+                        None => (param, ParamType::Str),
+                    };
+                    Segment::Param(name.to_string(), param_type)
+                }
+                None => Segment::Literal(s.to_string()),
+            })
+            .collect();
+
+        Route {
+            http_method: http_method.to_ascii_uppercase(),
+            segments,
+            handler: handler.to_string(),
+            raw_request: None,
+            query_types: Vec::new(),
+            raw_response: None,
+            raw_stream: None,
+            no_input: false,
+        }
+    }
+
+    // 未提供校验和 header 时由网关计算 CRC32; body 超过 max_len 时直接拒绝
+    pub fn raw_request(mut self, field: &str, checksum_field: Option<&str>, max_len: usize) -> Self {
+        self.raw_request = Some((field.to_string(), checksum_field.map(str::to_string), max_len));
+        self
+    }
+
+    // 声明 query 参数的类型, 未声明的保持字符串
+    pub fn query_param(mut self, name: &str, param_type: ParamType) -> Self {
+        self.query_types.push((name.to_string(), param_type));
+        self
+    }
+
+    pub fn raw_response(mut self, field: &str) -> Self {
+        self.raw_response = Some(field.to_string());
+        self
+    }
+
+    pub fn raw_stream(mut self, field: &str) -> Self {
+        self.raw_stream = Some(field.to_string());
+        self
+    }
+
+    // handler 不接收参数
+    pub fn no_input(mut self) -> Self {
+        self.no_input = true;
+        self
+    }
+
+    pub fn handler(&self) -> &str {
+        &self.handler
+    }
+
+    // 原始 body 按路由声明的上限, 其余按 handler 的帧上限
+    fn body_limit(&self, frame_limit: usize) -> usize {
+        match &self.raw_request {
+            Some((_, _, max_len)) => *max_len,
+            None => frame_limit,
+        }
+    }
+
+    fn matches(&self, method: &str, path: &str) -> Option<Vec<(String, ParamType, String)>> {
+        if method != self.http_method {
+            return None;
+        }
+
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name, param_type) => params.push((name.clone(), *param_type, percent_decode(part, false))),
+            }
+        }
+        Some(params)
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    content_length: usize,
+    keep_alive: bool,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug)]
+enum HttpError {
+    // 写回错误响应, 连接可继续使用
+    Status(u16, String),
+    // 响应已部分发出或 body 未读完, 只能关闭连接
+    Close,
+}
+
+impl ConnState {
+    // HTTP/1.1, 支持 keep-alive; body 只支持 Content-Length
+    pub async fn serve_http<S: Socket>(
+        self: &Arc<Self>,
+        mut stream: S,
+        connection_guard: Option<ConnectionGuard>,
+        context: RequestContext,
+    ) {
+        if connection_guard.is_none() {
+            warn!("too many connections, reject {}", context.peer_ip);
+            let _ = write_error(&mut stream, 503, "too many connections", false).await;
+            return;
+        }
+
+        let mut buffer = Vec::new();
+        loop {
+            let request = match read_head(&mut stream, &mut buffer).await {
+                Ok(Some(request)) => request,
+                Ok(None) => return,
+                Err(HttpError::Status(status, msg)) => {
+                    let _ = write_error(&mut stream, status, &msg, false).await;
+                    return;
+                }
+                Err(HttpError::Close) => return,
+            };
+
+            // WebSocket 连接升级后按原生协议帧收发, 直到任一端关闭
+            if request
+                .header("Upgrade")
+                .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+            {
+                if let Err(HttpError::Status(status, msg)) =
+                    upgrade_websocket(&mut stream, &request, &self.ws_path, &self.ws_allowed_origins).await
+                {
+                    let _ = write_error(&mut stream, status, &msg, false).await;
+                    return;
+                }
+                debug!("websocket connection from {}", context.peer_ip);
+                let (reader, writer) = transport::websocket(stream, buffer);
+                self.serve_frames(reader, writer, context, true).await;
+                return;
+            }
+
+            let keep_alive = request.keep_alive;
+            match self.handle_http(&mut stream, &mut buffer, request, &context).await {
+                Ok(()) => {}
+                Err(HttpError::Status(status, msg)) => {
+                    if write_error(&mut stream, status, &msg, keep_alive).await.is_err() {
+                        return;
+                    }
+                }
+                Err(HttpError::Close) => return,
+            }
+            if !keep_alive {
+                return;
+            }
+        }
+    }
+
+    async fn handle_http<S: Socket>(
+        &self,
+        stream: &mut S,
+        buffer: &mut Vec<u8>,
+        request: HttpRequest,
+        context: &RequestContext,
+    ) -> Result<(), HttpError> {
+        let (route, params) = self
+            .routes
+            .iter()
+            .find_map(|route| route.matches(&request.method, &request.path).map(|params| (route, params)))
+            .ok_or_else(|| HttpError::Status(404, format!("no route for {} {}", request.method, request.path)))?;
+
+        let limit = route.body_limit(
+            self.method_frame_limits
+                .get(route.handler())
+                .copied()
+                .unwrap_or(self.max_frame_size),
+        );
+        if request.content_length > limit {
+            // 未读取的 body 会破坏后续请求, 直接关闭连接
+            let _ = write_error(stream, 413, &format!("{}: body exceeds {limit} bytes", ErrorCode::FrameTooLarge), false).await;
+            return Err(HttpError::Close);
+        }
+        let body = read_body(stream, buffer, request.content_length).await?;
+
+        let payload = build_payload(route, &request, params, body)?;
+        let control_block = match request.header("Authorization").and_then(|auth| auth.strip_prefix("Bearer ")) {
+            Some(jwt) => serde_json::to_string(&ControlBlock {
+                jwt: jwt.trim().to_string(),
+                exp: 0,
+            })
+            .map(|block| general_purpose::STANDARD.encode(block))
+            .unwrap_or_else(|_| ".".to_string()),
+            None => ".".to_string(),
+        };
+        let frame = format!(
+            "{} {} {}",
+            route.handler(),
+            control_block,
+            general_purpose::STANDARD.encode(payload)
+        );
+
+        let Some((result, _permit)) = self.dispatch(frame, context.clone()).await else {
+            return Err(HttpError::Status(404, format!("method {} not registered", route.handler())));
+        };
+        trace!("Http resp: {:?}", result);
+
+        if !result.success {
+            let msg = result.payload.unwrap_or_default();
+            return Err(HttpError::Status(failure_status(&msg), msg));
+        }
+
+        let keep_alive = request.keep_alive;
+        let io_err = |e: std::io::Error| {
+            warn!("Failed to send msg: {}", e);
+            HttpError::Close
+        };
+        if let Some(field) = &route.raw_stream {
+            return self.write_stream(stream, result, field, keep_alive).await;
+        }
+        if let Some(field) = &route.raw_response {
+            let data = extract_bytes(result.payload.as_deref().unwrap_or_default(), field)
+                .ok_or_else(|| HttpError::Status(500, format!("response has no {field} field")))?;
+            return write_response(stream, 200, "application/octet-stream", &data, keep_alive)
+                .await
+                .map_err(io_err);
+        }
+
+        match (result.payload, result.control_block) {
+            (Some(payload), _) => {
+                // handler 的 payload 可能是 JSON 也可能是普通字符串或数字
+                let body = match serde_json::from_str::<Value>(&payload) {
+                    Ok(_) => payload,
+                    Err(_) => Value::String(payload).to_string(),
+                };
+                write_response(stream, 200, "application/json", body.as_bytes(), keep_alive)
+                    .await
+                    .map_err(io_err)
+            }
+            (None, Some(block)) => {
+                let body = serde_json::to_string(&block).unwrap_or_default();
+                write_response(stream, 200, "application/json", body.as_bytes(), keep_alive)
+                    .await
+                    .map_err(io_err)
+            }
+            (None, None) => write_response(stream, 204, "application/json", b"", keep_alive)
+                .await
+                .map_err(io_err),
+        }
+    }
+
+    async fn write_stream<S: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        result: ReturnCode,
+        field: &str,
+        keep_alive: bool,
+    ) -> Result<(), HttpError> {
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\nConnection: {}\r\n\r\n",
+            if keep_alive { "keep-alive" } else { "close" }
+        );
+        let io_err = |e: std::io::Error| {
+            warn!("Failed to send msg: {}", e);
+            HttpError::Close
+        };
+        write_timeout(stream, head.as_bytes()).await.map_err(io_err)?;
+
+        if let Some(mut frames) = result.stream {
+            while let Some(frame) = frames.recv().await {
+                if !frame.success {
+                    // 状态码已发出, 不写结束块使客户端得知传输失败
+                    warn!("stream aborted: {:?}", frame.payload);
+                    return Err(HttpError::Close);
+                }
+                let Some(data) = frame.payload.as_deref().and_then(|payload| extract_bytes(payload, field)) else {
+                    continue;
+                };
+                if data.is_empty() {
+                    continue;
+                }
+                write_timeout(stream, format!("{:x}\r\n", data.len()).as_bytes()).await.map_err(io_err)?;
+                write_timeout(stream, &data).await.map_err(io_err)?;
+                write_timeout(stream, b"\r\n").await.map_err(io_err)?;
+            }
+        }
+        write_timeout(stream, b"0\r\n\r\n").await.map_err(io_err)
+    }
+}
+
+async fn upgrade_websocket<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &HttpRequest,
+    ws_path: &str,
+    allowed_origins: &[String],
+) -> Result<(), HttpError> {
+    if request.method != "GET" || request.path != ws_path {
+        return Err(HttpError::Status(404, format!("no websocket endpoint at {}", request.path)));
+    }
+    let Some(key) = request.header("Sec-WebSocket-Key") else {
+        return Err(HttpError::Status(400, "missing Sec-WebSocket-Key".to_string()));
+    };
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return Err(HttpError::Status(426, "unsupported Sec-WebSocket-Version, expected 13".to_string()));
+    }
+    if let Some(origin) = request.header("Origin")
+        && !origin_allowed(origin, request.header("Host"), allowed_origins)
+    {
+        return Err(HttpError::Status(403, format!("origin {origin} not allowed")));
+    }
+
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        transport::ws_accept_key(key)
+    );
+    write_timeout(stream, head.as_bytes()).await.map_err(|e| {
+        warn!("Failed to send msg: {}", e);
+        HttpError::Close
+    })
+}
+
+// 浏览器总会带 Origin, 未配置允许列表时只接受与 Host 相同的来源, 防止其他站点借用户的连接发起请求
+fn origin_allowed(origin: &str, host: Option<&str>, allowed_origins: &[String]) -> bool {
+    if !allowed_origins.is_empty() {
+        return allowed_origins.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin));
+    }
+    let origin_host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .unwrap_or(origin);
+    host.is_some_and(|host| host.eq_ignore_ascii_case(origin_host))
+}
+
+// 读取请求行与 header, 连接正常关闭时返回 None; buffer 中保留已读但未处理的数据
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S, buffer: &mut Vec<u8>) -> Result<Option<HttpRequest>, HttpError> {
+    let head_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            return Err(HttpError::Status(431, "request header too large".to_string()));
+        }
+
+        let mut temp_buffer = [0; 1024];
+        let n = match read_timeout(stream, &mut temp_buffer).await {
+            Ok(n) => n,
+            Err(e) => {
+                debug!("http connection closed: {}", e);
+                return Ok(None);
+            }
+        };
+        if n == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&temp_buffer[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    buffer.drain(..head_end + 4);
+
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(HttpError::Status(400, format!("invalid request line: {request_line}")));
+    };
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (target, ""),
+    };
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key, true), percent_decode(value, true))
+        })
+        .collect();
+
+    let mut request = HttpRequest {
+        method: method.to_ascii_uppercase(),
+        path: path.to_string(),
+        query,
+        headers,
+        content_length: 0,
+        keep_alive: version == "HTTP/1.1",
+    };
+
+    if let Some(connection) = request.header("Connection") {
+        if connection.eq_ignore_ascii_case("close") {
+            request.keep_alive = false;
+        } else if connection.eq_ignore_ascii_case("keep-alive") {
+            request.keep_alive = true;
+        }
+    }
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(HttpError::Status(411, "chunked request body is not supported, send Content-Length".to_string()));
+    }
+    if let Some(length) = request.header("Content-Length") {
+        request.content_length = length
+            .parse()
+            .map_err(|_| HttpError::Status(400, format!("invalid Content-Length: {length}")))?;
+    }
+
+    Ok(Some(request))
+}
+
+async fn read_body<S: AsyncRead + Unpin>(stream: &mut S, buffer: &mut Vec<u8>, length: usize) -> Result<Vec<u8>, HttpError> {
+    while buffer.len() < length {
+        let mut temp_buffer = [0; 8192];
+        let n = read_timeout(stream, &mut temp_buffer).await.map_err(|e| {
+            warn!("Failed to read msg: {}", e);
+            HttpError::Close
+        })?;
+        if n == 0 {
+            return Err(HttpError::Close);
+        }
+        buffer.extend_from_slice(&temp_buffer[..n]);
+    }
+    Ok(buffer.drain(..length).collect())
+}
+
+fn build_payload(
+    route: &Route,
+    request: &HttpRequest,
+    params: Vec<(String, ParamType, String)>,
+    body: Vec<u8>,
+) -> Result<String, HttpError> {
+    if route.no_input {
+        return Ok("0".to_string());
+    }
+
+    let mut payload = Map::new();
+    match &route.raw_request {
+        Some((_, Some(checksum_field), _)) => {
+            let block_checksum = match request.header("X-Checksum") {
+                Some(value) => value
+                    .parse::<u32>()
+                    .map_err(|_| HttpError::Status(400, format!("invalid X-Checksum: {value}")))?,
+                None => checksum(&body),
+            };
+            payload.insert(checksum_field.clone(), Value::from(block_checksum));
+        }
+        Some(_) => {}
+        None if !body.is_empty() => {
+            match serde_json::from_slice::<Value>(&body) {
+                Ok(Value::Object(object)) => payload.extend(object),
+                Ok(_) => return Err(HttpError::Status(400, "request body must be a JSON object".to_string())),
+                Err(e) => return Err(HttpError::Status(400, format!("invalid JSON body: {e}"))),
+            }
+        }
+        None => {}
+    }
+
+    // 路径参数优先于 query 与 body
+    for (key, value) in request.query.iter().cloned() {
+        let param_type = route
+            .query_types
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map_or(ParamType::Str, |(_, param_type)| *param_type);
+        let value = param_type.convert(&key, value)?;
+        payload.insert(key, value);
+    }
+    for (key, param_type, value) in params {
+        let value = param_type.convert(&key, value)?;
+        payload.insert(key, value);
+    }
+
+    let mut payload = Value::Object(payload).to_string();
+    if let Some((field, _, _)) = &route.raw_request {
+        // 原始字节直接写成 JSON 数组, 不为每个字节构造 Value
+        payload = raw_field_payload(&payload, field, &body);
+    }
+    Ok(payload)
+}
+
+// 在已序列化的 JSON 对象开头插入 "field":[b0,b1,...]
+fn raw_field_payload(object: &str, field: &str, bytes: &[u8]) -> String {
+    let mut payload = String::with_capacity(object.len() + field.len() + bytes.len() * 4 + 8);
+    payload.push('{');
+    payload.push_str(&Value::String(field.to_string()).to_string());
+    payload.push_str(":[");
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            payload.push(',');
+        }
+        let _ = write!(payload, "{byte}");
+    }
+    payload.push(']');
+    let rest = &object[1..];
+    if rest != "}" {
+        payload.push(',');
+    }
+    payload.push_str(rest);
+    payload
+}
+
+fn extract_bytes(payload: &str, field: &str) -> Option<Vec<u8>> {
+    let value: Value = serde_json::from_str(payload).ok()?;
+    value
+        .get(field)?
+        .as_array()?
+        .iter()
+        .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+        .collect()
+}
+
+fn failure_status(msg: &str) -> u16 {
+    let code = msg.split(':').next().unwrap_or_default();
+    if code == ErrorCode::RateLimited.as_str() || code == ErrorCode::AccountLocked.as_str() {
+        429
+    } else if code == ErrorCode::ServerBusy.as_str() {
+        503
+    } else if code == ErrorCode::FrameTooLarge.as_str()
+        || code == ErrorCode::BlockTooLarge.as_str()
+        || code == ErrorCode::FileTooLarge.as_str()
+    {
+        413
+    } else if code == ErrorCode::Unauthorized.as_str() {
+        401
+    } else if code == ErrorCode::PermissionDenied.as_str() {
+        403
+    } else {
+        400
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+async fn write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        status,
+        reason_phrase(status),
+        content_type,
+        body.len(),
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    write_timeout(stream, head.as_bytes()).await?;
+    write_timeout(stream, body).await
+}
+
+async fn write_error<S: AsyncWrite + Unpin>(stream: &mut S, status: u16, msg: &str, keep_alive: bool) -> std::io::Result<()> {
+    let body = serde_json::json!({ "error": msg }).to_string();
+    write_response(stream, status, "application/json", body.as_bytes(), keep_alive).await
+}
+
+fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match bytes.get(i + 1..i + 3).and_then(hex_byte) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                None => {
+                    decoded.push(b'%');
+                    i += 1;
+                }
+            },
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// 只接受两位十六进制数字, from_str_radix 会接受 "+1" 这样的写法
+fn hex_byte(hex: &[u8]) -> Option<u8> {
+    let digit = |byte: u8| (byte as char).to_digit(16);
+    Some((digit(hex[0])? * 16 + digit(hex[1])?) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_route() -> Route {
+        Route::new("put", "/files/{file_id:int}/blocks/{block_id:int}", "send").raw_request(
+            "block_payload",
+            Some("block_checksum"),
+            1024,
+        )
+    }
+
+    fn request(method: &str, query: &[(&str, &str)], headers: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest {
+            method: method.to_string(),
+            path: String::new(),
+            query: query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            content_length: 0,
+            keep_alive: true,
+        }
+    }
+
+    #[test]
+    fn route_matches_path_params() {
+        let route = block_route();
+        let params = route.matches("PUT", "/files/3/blocks/4").unwrap();
+        assert_eq!(params, vec![
+            ("file_id".to_string(), ParamType::Int, "3".to_string()),
+            ("block_id".to_string(), ParamType::Int, "4".to_string()),
+        ]);
+
+        let route = Route::new("GET", "/dirs/{path}", "list_dir");
+        let params = route.matches("GET", "/dirs/a%20b+c").unwrap();
+        assert_eq!(params[0].2, "a b+c");
+    }
+
+    #[test]
+    fn route_rejects_method_and_path_mismatch() {
+        let route = block_route();
+        assert!(route.matches("GET", "/files/3/blocks/4").is_none());
+        assert!(route.matches("PUT", "/files/3/blocks").is_none());
+        assert!(route.matches("PUT", "/files/3/chunks/4").is_none());
+        assert!(route.matches("PUT", "/files/3/blocks/4/5").is_none());
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%", false), "100%");
+        assert_eq!(percent_decode("%zz%4", false), "%zz%4");
+        assert_eq!(percent_decode("%+1%-1", false), "%+1%-1");
+        assert_eq!(percent_decode("%E4%BD%A0+1", true), "你 1");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("%FF", false), "\u{FFFD}");
+    }
+
+    #[test]
+    fn raw_body_limited_by_route() {
+        assert_eq!(block_route().body_limit(64 * 1024 * 1024), 1024);
+        assert_eq!(Route::new("POST", "/files", "presend").body_limit(4096), 4096);
+    }
+
+    #[test]
+    fn raw_body_written_as_byte_array() {
+        assert_eq!(raw_field_payload("{}", "data", &[1, 255]), r#"{"data":[1,255]}"#);
+        assert_eq!(raw_field_payload(r#"{"a":1}"#, "data", &[]), r#"{"data":[],"a":1}"#);
+
+        let payload = build_payload(
+            &block_route(),
+            &request("PUT", &[], &[]),
+            vec![("file_id".to_string(), ParamType::Int, "3".to_string())],
+            b"abc".to_vec(),
+        )
+        .unwrap();
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["block_payload"], serde_json::json!([97, 98, 99]));
+        assert_eq!(payload["block_checksum"], checksum(b"abc"));
+        assert_eq!(payload["file_id"], 3);
+    }
+
+    #[test]
+    fn build_payload_types_only_declared_params() {
+        let route = Route::new("GET", "/files/search", "search_files").query_param("limit", ParamType::Int);
+        let payload = build_payload(&route, &request("GET", &[("query", "2024"), ("limit", "5")], &[]), vec![], vec![])
+            .unwrap();
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["query"], "2024");
+        assert_eq!(payload["limit"], 5);
+
+        let result = build_payload(&route, &request("GET", &[("limit", "many")], &[]), vec![], vec![]);
+        assert!(matches!(result, Err(HttpError::Status(400, _))));
+        let result = build_payload(&block_route(), &request("PUT", &[], &[("X-Checksum", "x")]), vec![], vec![]);
+        assert!(matches!(result, Err(HttpError::Status(400, _))));
+    }
+
+    #[test]
+    fn failure_status_maps_error_codes() {
+        let status = |code: ErrorCode| failure_status(&format!("{code}: details"));
+        assert_eq!(status(ErrorCode::RateLimited), 429);
+        assert_eq!(status(ErrorCode::AccountLocked), 429);
+        assert_eq!(status(ErrorCode::ServerBusy), 503);
+        assert_eq!(status(ErrorCode::FrameTooLarge), 413);
+        assert_eq!(status(ErrorCode::BlockTooLarge), 413);
+        assert_eq!(status(ErrorCode::FileTooLarge), 413);
+        assert_eq!(status(ErrorCode::Unauthorized), 401);
+        assert_eq!(status(ErrorCode::PermissionDenied), 403);
+        // 未带错误码的消息不按文字猜测
+        assert_eq!(failure_status("permission denied"), 400);
+        assert_eq!(failure_status("file not found"), 400);
+    }
+}
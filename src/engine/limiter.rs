@@ -0,0 +1,184 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+// 超过该数量时清理已回满的桶, 避免 key 无限增长
+const MAX_IDLE_BUCKETS: usize = 10000;
+
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    // rate: 每秒补充的令牌数, burst: 桶容量
+    pub fn new(rate: f64, burst: f64) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1.0),
+            buckets: DashMap::new(),
+        }
+    }
+
+    pub fn try_acquire(&self, key: &str) -> bool {
+        self.try_acquire_at(key, Instant::now())
+    }
+
+    fn try_acquire_at(&self, key: &str, now: Instant) -> bool {
+        if self.buckets.len() > MAX_IDLE_BUCKETS {
+            self.cleanup();
+        }
+
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: self.burst,
+            last: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn cleanup(&self) {
+        let full_after = Duration::from_secs_f64(self.burst / self.rate.max(f64::MIN_POSITIVE));
+        let now = Instant::now();
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.last) < full_after);
+    }
+}
+
+// 并发连接数限制, 全局与单 IP 上限为 0 时不限制
+pub struct ConnectionLimiter {
+    max_total: usize,
+    max_per_ip: usize,
+    total: AtomicUsize,
+    per_ip: DashMap<String, usize>,
+}
+
+// 连接结束时自动归还计数
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: String,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_total: usize, max_per_ip: usize) -> Self {
+        ConnectionLimiter {
+            max_total,
+            max_per_ip,
+            total: AtomicUsize::new(0),
+            per_ip: DashMap::new(),
+        }
+    }
+
+    pub fn try_acquire(self: &Arc<Self>, ip: &str) -> Option<ConnectionGuard> {
+        let total = self.total.fetch_add(1, Ordering::AcqRel);
+        if self.max_total > 0 && total >= self.max_total {
+            self.total.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+
+        let mut count = self.per_ip.entry(ip.to_string()).or_insert(0);
+        if self.max_per_ip > 0 && *count >= self.max_per_ip {
+            drop(count);
+            self.total.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionGuard {
+            limiter: Arc::clone(self),
+            ip: ip.to_string(),
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter
+            .per_ip
+            .remove_if_mut(&self.ip, |_, count| {
+                *count -= 1;
+                *count == 0
+            });
+        self.limiter.total.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_exhausts_burst_and_refills() {
+        let limiter = RateLimiter::new(2.0, 3.0);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at("10.0.0.1", start));
+        }
+        assert!(!limiter.try_acquire_at("10.0.0.1", start));
+        // 其他 key 有独立的桶
+        assert!(limiter.try_acquire_at("10.0.0.2", start));
+
+        // 每秒补充 2 个令牌
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.try_acquire_at("10.0.0.1", later));
+        assert!(!limiter.try_acquire_at("10.0.0.1", later));
+
+        // 补充不超过桶容量
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at("10.0.0.1", much_later));
+        }
+        assert!(!limiter.try_acquire_at("10.0.0.1", much_later));
+    }
+
+    #[test]
+    fn connection_limiter_caps_per_ip() {
+        let limiter = Arc::new(ConnectionLimiter::new(0, 2));
+        let _a = limiter.try_acquire("10.0.0.1").unwrap();
+        let _b = limiter.try_acquire("10.0.0.1").unwrap();
+        assert!(limiter.try_acquire("10.0.0.1").is_none());
+        assert!(limiter.try_acquire("10.0.0.2").is_some());
+    }
+
+    #[test]
+    fn connection_limiter_caps_total() {
+        let limiter = Arc::new(ConnectionLimiter::new(2, 0));
+        let _a = limiter.try_acquire("10.0.0.1").unwrap();
+        let _b = limiter.try_acquire("10.0.0.2").unwrap();
+        assert!(limiter.try_acquire("10.0.0.3").is_none());
+        // 被拒绝的连接不占用计数
+        assert_eq!(limiter.total.load(Ordering::Acquire), 2);
+    }
+
+    #[test]
+    fn connection_guard_releases_on_drop() {
+        let limiter = Arc::new(ConnectionLimiter::new(1, 1));
+        let guard = limiter.try_acquire("10.0.0.1").unwrap();
+        assert!(limiter.try_acquire("10.0.0.1").is_none());
+        drop(guard);
+        assert_eq!(limiter.total.load(Ordering::Acquire), 0);
+        assert!(limiter.per_ip.is_empty());
+        assert!(limiter.try_acquire("10.0.0.1").is_some());
+    }
+}
@@ -1,2 +1,9 @@
 pub mod return_code;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod limiter;
+pub mod context;
+pub mod tls;
+pub mod conn;
+pub mod proxy;
+pub mod http;
+pub mod transport;
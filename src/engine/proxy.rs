@@ -0,0 +1,260 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// v1 头部最长 107 字节 (含 CRLF)
+const V1_MAX_LEN: usize = 107;
+
+// 可信代理的地址段, 如 "10.0.0.0/8", 省略前缀长度表示单个地址
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|e| format!("invalid address {addr}: {e}"))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|e| format!("invalid prefix {prefix}: {e}"))?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(format!("prefix {prefix} too long for {addr}"));
+        }
+
+        // 对端地址按 IPv4 比较, ::ffff:a.b.c.d 形式的配置也转换为 IPv4
+        match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() && prefix >= 96 => Ok(Cidr { addr: IpAddr::V4(v4), prefix: prefix - 96 }),
+            _ => Ok(Cidr { addr, prefix }),
+        }
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn is_trusted(trusted: &[Cidr], peer_ip: &str) -> bool {
+    peer_ip
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| trusted.iter().any(|cidr| cidr.contains(ip)))
+}
+
+// 读取 PROXY protocol v1/v2 头部, 返回真实客户端地址; LOCAL/UNKNOWN 返回 None 表示沿用连接地址
+// 逐段按长度读取, 不会多读属于 TLS 握手的数据
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, String> {
+    let mut prefix = [0u8; 12];
+    read_exact(stream, &mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if prefix.starts_with(b"PROXY ") {
+        read_v1(stream, &prefix).await
+    } else {
+        Err("missing PROXY protocol header".to_string())
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(stream: &mut R, prefix: &[u8]) -> Result<Option<SocketAddr>, String> {
+    let mut line = prefix.to_vec();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err("PROXY v1 header too long".to_string());
+        }
+        read_exact(stream, &mut byte).await?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| "invalid PROXY v1 header".to_string())?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| format!("invalid PROXY v1 source {src}"))?;
+            let port: u16 = src_port.parse().map_err(|_| format!("invalid PROXY v1 port {src_port}"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(format!("invalid PROXY v1 header: {line}")),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, String> {
+    let mut header = [0u8; 4];
+    read_exact(stream, &mut header).await?;
+    let [ver_cmd, family, len_hi, len_lo] = header;
+    if ver_cmd >> 4 != 2 {
+        return Err(format!("unsupported PROXY version {}", ver_cmd >> 4));
+    }
+
+    let mut addrs = vec![0u8; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    read_exact(stream, &mut addrs).await?;
+
+    match ver_cmd & 0x0f {
+        // LOCAL 命令为代理自身的健康检查等连接
+        0x0 => return Ok(None),
+        0x1 => {}
+        cmd => return Err(format!("unsupported PROXY v2 command {cmd}")),
+    }
+
+    match family >> 4 {
+        // AF_INET: src(4) dst(4) src_port(2) dst_port(2)
+        1 if addrs.len() >= 12 => {
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6: src(16) dst(16) src_port(2) dst_port(2)
+        2 if addrs.len() >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[..16]).unwrap());
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(ip), port)))
+        }
+        1 | 2 => Err("truncated PROXY v2 address block".to_string()),
+        // AF_UNSPEC / AF_UNIX 无法提供 IP
+        _ => Ok(None),
+    }
+}
+
+async fn read_exact<R: AsyncRead + Unpin>(stream: &mut R, buf: &mut [u8]) -> Result<(), String> {
+    stream
+        .read_exact(buf)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("read PROXY header err: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(ver_cmd: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[ver_cmd, family]);
+        header.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        header.extend_from_slice(addrs);
+        header
+    }
+
+    async fn parse(mut data: &[u8]) -> Result<Option<SocketAddr>, String> {
+        read_header(&mut data).await
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4_and_tcp6() {
+        let addr = parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 5000 443\r\n").await.unwrap();
+        assert_eq!(addr, Some("192.0.2.1:5000".parse().unwrap()));
+
+        let addr = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 5000 443\r\n").await.unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:5000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v1_rejects_truncated_oversize_and_garbage() {
+        assert!(parse(b"PROXY TCP4 192.0.2.1").await.is_err());
+        let mut long = b"PROXY TCP4 ".to_vec();
+        long.resize(200, b'1');
+        long.extend_from_slice(b"\r\n");
+        assert!(parse(&long).await.is_err());
+        assert!(parse(b"PROXY TCP4 not-an-ip 198.51.100.1 5000 443\r\n").await.is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn v1_leaves_following_bytes_unread() {
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n\x16\x03\x01";
+        read_header(&mut stream).await.unwrap();
+        assert_eq!(stream, b"\x16\x03\x01");
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_inet_and_inet6() {
+        let mut addrs = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        addrs.extend_from_slice(&5000u16.to_be_bytes());
+        addrs.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(parse(&v2(0x21, 0x11, &addrs)).await.unwrap(), Some("192.0.2.1:5000".parse().unwrap()));
+
+        let mut addrs = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        addrs.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addrs.extend_from_slice(&5000u16.to_be_bytes());
+        addrs.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(parse(&v2(0x21, 0x21, &addrs)).await.unwrap(), Some("[2001:db8::1]:5000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn v2_local_and_unspec() {
+        assert_eq!(parse(&v2(0x20, 0x00, &[])).await.unwrap(), None);
+        // LOCAL 忽略地址块
+        assert_eq!(parse(&v2(0x20, 0x11, &[0; 12])).await.unwrap(), None);
+        assert_eq!(parse(&v2(0x21, 0x00, &[])).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v2_rejects_bad_version_command_and_truncation() {
+        assert!(parse(&v2(0x11, 0x11, &[0; 12])).await.is_err());
+        assert!(parse(&v2(0x22, 0x11, &[0; 12])).await.is_err());
+        assert!(parse(&v2(0x21, 0x11, &[0; 4])).await.is_err());
+        assert!(parse(&v2(0x21, 0x21, &[0; 12])).await.is_err());
+
+        // 长度字段超过实际数据
+        let mut header = v2(0x21, 0x11, &[0; 12]);
+        header.truncate(header.len() - 4);
+        assert!(parse(&header).await.is_err());
+        assert!(parse(&V2_SIGNATURE[..8]).await.is_err());
+    }
+
+    #[test]
+    fn cidr_matching() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("11.0.0.1".parse().unwrap()));
+
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains("2001:db8::1".parse().unwrap()));
+        assert!(!net.contains("10.0.0.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn cidr_canonicalizes_mapped_addresses() {
+        let single: Cidr = "::ffff:10.0.0.1".parse().unwrap();
+        assert!(single.contains("10.0.0.1".parse().unwrap()));
+        assert!(!single.contains("10.0.0.2".parse().unwrap()));
+
+        let net: Cidr = "::ffff:10.0.0.0/104".parse().unwrap();
+        assert!(net.contains("10.200.0.1".parse().unwrap()));
+        assert!(is_trusted(&[net], "10.200.0.1"));
+    }
+}
@@ -1,13 +1,50 @@
-use std::pin::Pin;
+use std::{fmt, pin::Pin};
 use async_trait::async_trait;
+use tokio::sync::mpsc;
 
 use crate::{control_block::ControlBlock, engine::engine::Engine};
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct ReturnCode {
     pub success: bool,
     pub payload: Option<String>,
     pub control_block: Option<ControlBlock>,
+    // 后续响应帧, 在首个响应之后依次写回, 发送端关闭即结束
+    pub stream: Option<mpsc::Receiver<ReturnCode>>,
+}
+
+// 失败响应的 payload 以错误码开头, 如 "ERR_FRAME_TOO_LARGE: ..."
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    FrameTooLarge,
+    BlockTooLarge,
+    FileTooLarge,
+    RateLimited,
+    AccountLocked,
+    ServerBusy,
+    Unauthorized,
+    PermissionDenied,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::FrameTooLarge => "ERR_FRAME_TOO_LARGE",
+            ErrorCode::BlockTooLarge => "ERR_BLOCK_TOO_LARGE",
+            ErrorCode::FileTooLarge => "ERR_FILE_TOO_LARGE",
+            ErrorCode::RateLimited => "ERR_RATE_LIMITED",
+            ErrorCode::AccountLocked => "ERR_ACCOUNT_LOCKED",
+            ErrorCode::ServerBusy => "ERR_SERVER_BUSY",
+            ErrorCode::Unauthorized => "ERR_UNAUTHORIZED",
+            ErrorCode::PermissionDenied => "ERR_PERMISSION_DENIED",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,402 @@
+use std::{
+    fs,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use log::*;
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    ssl::{
+        AlpnError, NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype,
+        SslMethod, SslOptions, SslSessionCacheMode, SslVerifyMode, SslVersion,
+    },
+    x509::{
+        X509, X509Name, X509NameBuilder,
+        extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName},
+    },
+};
+
+// 客户端证书校验模式
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ClientAuth {
+    #[default]
+    None,
+    Optional,
+    Required,
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ClientAuth::None),
+            "optional" => Ok(ClientAuth::Optional),
+            "required" => Ok(ClientAuth::Required),
+            _ => Err(format!("unknown client auth mode: {s}")),
+        }
+    }
+}
+
+pub fn parse_tls_version(version: &str) -> Result<SslVersion, String> {
+    match version {
+        "1.2" => Ok(SslVersion::TLS1_2),
+        "1.3" => Ok(SslVersion::TLS1_3),
+        _ => Err(format!("unsupported TLS version: {version}")),
+    }
+}
+
+// 按 SNI 主机名选择的证书, server_name 支持 "*.example.com" 形式的通配
+#[derive(Debug, Clone)]
+pub struct SniCert {
+    pub server_name: String,
+    pub private_key_file: String,
+    pub cert_file: String,
+}
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub private_key_file: String,
+    pub cert_file: String,
+    pub client_ca_file: Option<String>,
+    pub client_auth: ClientAuth,
+    // 未设置的项沿用 mozilla_intermediate 的默认值
+    pub min_version: Option<SslVersion>,
+    pub cipher_list: Option<String>,
+    pub ciphersuites: Option<String>,
+    pub groups: Option<String>,
+    pub session_tickets: bool,
+    pub session_cache: bool,
+    pub alpn_protocols: Vec<String>,
+    pub sni_certs: Vec<SniCert>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            private_key_file: String::new(),
+            cert_file: String::new(),
+            client_ca_file: None,
+            client_auth: ClientAuth::None,
+            min_version: None,
+            cipher_list: None,
+            ciphersuites: None,
+            groups: None,
+            session_tickets: true,
+            session_cache: true,
+            alpn_protocols: Vec::new(),
+            sni_certs: Vec::new(),
+        }
+    }
+}
+
+// 新连接通过 read 取当前 acceptor, 重载时整体替换, 已建立的连接不受影响
+pub type SharedAcceptor = Arc<RwLock<Arc<SslAcceptor>>>;
+
+impl TlsConfig {
+    pub fn build_acceptor(&self) -> Result<SslAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+        let mut builder = self.acceptor_builder(&self.private_key_file, &self.cert_file)?;
+
+        if !self.sni_certs.is_empty() {
+            let mut contexts = Vec::with_capacity(self.sni_certs.len());
+            for sni in &self.sni_certs {
+                let context = self
+                    .acceptor_builder(&sni.private_key_file, &sni.cert_file)?
+                    .build()
+                    .into_context();
+                contexts.push((sni.server_name.to_ascii_lowercase(), context));
+            }
+            builder.set_servername_callback(move |ssl, _| {
+                // 未匹配任何主机名时使用默认证书
+                let Some(server_name) = ssl.servername(NameType::HOST_NAME) else {
+                    return Ok(());
+                };
+                let server_name = server_name.to_ascii_lowercase();
+                if let Some(context) = select_sni_context(&contexts, &server_name) {
+                    ssl.set_ssl_context(context).map_err(|_| SniError::ALERT_FATAL)?;
+                }
+                Ok(())
+            });
+        }
+
+        Ok(builder.build())
+    }
+
+    fn acceptor_builder(
+        &self,
+        private_key_file: &str,
+        cert_file: &str,
+    ) -> Result<SslAcceptorBuilder, Box<dyn std::error::Error + Send + Sync>> {
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        builder.set_private_key_file(private_key_file, SslFiletype::PEM)?;
+        builder.set_certificate_chain_file(cert_file)?;
+        builder.check_private_key()?;
+
+        if let Some(version) = self.min_version {
+            builder.set_min_proto_version(Some(version))?;
+        }
+        if let Some(cipher_list) = &self.cipher_list {
+            builder.set_cipher_list(cipher_list)?;
+        }
+        if let Some(ciphersuites) = &self.ciphersuites {
+            builder.set_ciphersuites(ciphersuites)?;
+        }
+        if let Some(groups) = &self.groups {
+            builder.set_groups_list(groups)?;
+        }
+        if !self.session_tickets {
+            builder.set_options(SslOptions::NO_TICKET);
+        }
+        if self.session_cache {
+            builder.set_session_id_context(b"rust_ssl_file_server")?;
+        } else {
+            builder.set_session_cache_mode(SslSessionCacheMode::OFF);
+        }
+
+        if !self.alpn_protocols.is_empty() {
+            let protocols = self.alpn_protocols.clone();
+            // 客户端未声明 ALPN 或没有共同协议时不协商, 兼容旧客户端
+            builder.set_alpn_select_callback(move |_, client| {
+                select_alpn(&protocols, client).ok_or(AlpnError::NOACK)
+            });
+        }
+
+        if self.client_auth != ClientAuth::None {
+            let Some(ca_file) = &self.client_ca_file else {
+                return Err("client auth enabled but no client CA file configured".into());
+            };
+            builder.set_ca_file(ca_file)?;
+            builder.set_client_ca_list(X509Name::load_client_ca_file(ca_file)?);
+
+            let mut mode = SslVerifyMode::PEER;
+            if self.client_auth == ClientAuth::Required {
+                mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+            }
+            builder.set_verify(mode);
+        }
+
+        Ok(builder)
+    }
+
+    fn files(&self) -> Vec<&str> {
+        let mut files = vec![self.private_key_file.as_str(), self.cert_file.as_str()];
+        if let Some(ca_file) = &self.client_ca_file {
+            files.push(ca_file);
+        }
+        for sni in &self.sni_certs {
+            files.push(&sni.private_key_file);
+            files.push(&sni.cert_file);
+        }
+        files
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        self.files()
+            .into_iter()
+            .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+            .collect()
+    }
+
+    // 重新加载证书, 失败时保留旧的 acceptor
+    pub fn reload(&self, acceptor: &SharedAcceptor) {
+        match self.build_acceptor() {
+            Ok(new_acceptor) => {
+                *acceptor.write().unwrap() = Arc::new(new_acceptor);
+                info!("TLS certificate reloaded from {}", self.cert_file);
+            }
+            Err(e) => {
+                error!("reload TLS certificate failed, keep the old one: {}", e);
+            }
+        }
+    }
+
+    // 轮询证书文件的修改时间, 并在收到 SIGHUP 时强制重载; interval 为 0 时只响应信号
+    pub async fn watch(self, acceptor: SharedAcceptor, interval: Duration) {
+        let mut last_modified = self.modified_times();
+        let mut ticker = tokio::time::interval(if interval.is_zero() {
+            Duration::from_secs(3600)
+        } else {
+            interval
+        });
+
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                warn!("failed to listen for SIGHUP: {}", e);
+                None
+            }
+        };
+
+        loop {
+            #[cfg(unix)]
+            let force = tokio::select! {
+                _ = ticker.tick() => false,
+                Some(_) = async {
+                    match hangup.as_mut() {
+                        Some(signal) => signal.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => true,
+            };
+            #[cfg(not(unix))]
+            let force = {
+                ticker.tick().await;
+                false
+            };
+
+            if force {
+                info!("SIGHUP received, reloading TLS certificate");
+                last_modified = self.modified_times();
+                self.reload(&acceptor);
+                continue;
+            }
+
+            if interval.is_zero() {
+                continue;
+            }
+
+            // 证书和私钥可能分两次写入, 全部文件都存在时才重载
+            let modified = self.modified_times();
+            if modified != last_modified && modified.iter().all(Option::is_some) {
+                last_modified = modified;
+                self.reload(&acceptor);
+            }
+        }
+    }
+}
+
+fn select_sni_context<'a>(contexts: &'a [(String, SslContext)], server_name: &str) -> Option<&'a SslContext> {
+    contexts
+        .iter()
+        .find(|(name, _)| name == server_name)
+        .or_else(|| {
+            contexts.iter().find(|(name, _)| {
+                name.strip_prefix("*.").is_some_and(|suffix| {
+                    server_name
+                        .split_once('.')
+                        .is_some_and(|(_, rest)| rest == suffix)
+                })
+            })
+        })
+        .map(|(_, context)| context)
+}
+
+// 按服务端的优先顺序在客户端列表 (长度前缀编码) 中选择协议, 返回值指向客户端列表
+fn select_alpn<'a>(protocols: &[String], client: &'a [u8]) -> Option<&'a [u8]> {
+    let mut offered = Vec::new();
+    let mut rest = client;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = len as usize;
+        if tail.len() < len {
+            break;
+        }
+        offered.push(&tail[..len]);
+        rest = &tail[len..];
+    }
+
+    protocols
+        .iter()
+        .find_map(|protocol| offered.iter().find(|p| **p == protocol.as_bytes()).copied())
+}
+
+// 开发模式: 证书或私钥不存在时生成自签名证书并保存, 打印指纹供客户端固定
+pub fn ensure_dev_cert(
+    private_key_file: &str,
+    cert_file: &str,
+    sans: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !Path::new(private_key_file).exists() || !Path::new(cert_file).exists() {
+        let (key_pem, cert_pem) = generate_self_signed(sans)?;
+        for file in [private_key_file, cert_file] {
+            if let Some(dir) = Path::new(file).parent() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        write_private(private_key_file, &key_pem)?;
+        fs::write(cert_file, &cert_pem)?;
+        warn!("generated self-signed certificate {} for {:?}, do not use it in production", cert_file, sans);
+    }
+
+    let cert = X509::from_pem(&fs::read(cert_file)?)?;
+    info!("certificate SHA-256 fingerprint: {}", fingerprint(&cert)?);
+    Ok(())
+}
+
+fn generate_self_signed(sans: &[String]) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error + Send + Sync>> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let common_name = sans.first().map(String::as_str).unwrap_or("localhost");
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    let serial = serial.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(365)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    let mut alt_names = SubjectAlternativeName::new();
+    for san in sans {
+        if san.parse::<IpAddr>().is_ok() {
+            alt_names.ip(san);
+        } else {
+            alt_names.dns(san);
+        }
+    }
+    let alt_names = alt_names.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(alt_names)?;
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    builder.append_extension(KeyUsage::new().critical().digital_signature().build()?)?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((key.private_key_to_pem_pkcs8()?, builder.build().to_pem()?))
+}
+
+fn write_private(path: &str, data: &[u8]) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::{io::Write, os::unix::fs::OpenOptionsExt};
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(data)
+    }
+    #[cfg(not(unix))]
+    {
+        fs::write(path, data)
+    }
+}
+
+pub fn fingerprint(cert: &X509) -> Result<String, openssl::error::ErrorStack> {
+    let digest = cert.digest(MessageDigest::sha256())?;
+    Ok(digest
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
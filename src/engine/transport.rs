@@ -0,0 +1,482 @@
+use std::{io, time::Duration};
+
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose};
+use log::*;
+use openssl::sha::sha1;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+use crate::utils::END_MARK;
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// 单次读写的超时, 与原先阻塞 socket 的读写超时一致
+pub const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 底层连接: TCP、UDS 或其上的 TLS
+pub trait Socket: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Socket for S {}
+
+pub async fn read_timeout<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut [u8]) -> io::Result<usize> {
+    tokio::time::timeout(IO_TIMEOUT, stream.read(buf))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+pub async fn write_timeout<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8]) -> io::Result<()> {
+    tokio::time::timeout(IO_TIMEOUT, stream.write_all(data))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    // 请求帧超过上限, 附带生效的上限; 调用方写回错误后关闭连接
+    TooLarge(usize),
+    // 对端关闭或违反协议; WebSocket 附带需要回复的关闭码
+    Closed(Option<u16>),
+}
+
+// 写端发出的消息, 原生协议只有响应帧
+#[derive(Debug, PartialEq)]
+pub enum Outgoing {
+    Frame(String),
+    Ping,
+    Pong(Vec<u8>),
+    Close(u16),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    // 一个完整的请求帧
+    Request(Vec<u8>),
+    // 需要写端回复的控制帧
+    Reply(Outgoing),
+    // 不需要处理的控制帧 (如 pong), 只说明连接仍然活跃
+    KeepAlive,
+}
+
+// 承载原生协议请求/响应帧的传输层, 原生 TLS 连接与 WebSocket 共用同一套分发逻辑
+// 根据已读到的数据 (方法名) 返回帧上限
+pub type FrameLimit = dyn Fn(&[u8]) -> usize + Sync;
+
+// 连接拆成读写两半: 读端在连接任务中解析请求, 写端按顺序写出经 channel 送来的响应
+#[async_trait]
+pub trait FrameReader: Send {
+    // 未读完的数据保存在内部缓冲区, 可以放在 timeout 或 select! 中取消后再次调用
+    async fn read_frame(&mut self, limit: &FrameLimit) -> Result<Incoming, FrameError>;
+}
+
+#[async_trait]
+pub trait FrameWriter: Send {
+    async fn write(&mut self, message: Outgoing) -> io::Result<()>;
+}
+
+// 读入更多数据, 对端关闭或出错时返回 false
+async fn fill<S: AsyncRead + Unpin>(stream: &mut S, buffer: &mut Vec<u8>) -> bool {
+    buffer.reserve(8192);
+    match stream.read_buf(buffer).await {
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Failed to read msg: {}", e);
+            false
+        }
+    }
+}
+
+pub fn native<S: Socket>(stream: S) -> (NativeReader<ReadHalf<S>>, NativeWriter<WriteHalf<S>>) {
+    let (reader, writer) = tokio::io::split(stream);
+    (NativeReader::new(reader), NativeWriter { stream: writer })
+}
+
+// 原生协议: 以 END_MARK 结束的文本帧
+pub struct NativeReader<R> {
+    stream: R,
+    buffer: Vec<u8>,
+    // buffer 中已查找过 END_MARK 的长度
+    scanned: usize,
+}
+
+impl<R> NativeReader<R> {
+    fn new(stream: R) -> Self {
+        NativeReader {
+            stream,
+            buffer: Vec::new(),
+            scanned: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> FrameReader for NativeReader<R> {
+    async fn read_frame(&mut self, limit: &FrameLimit) -> Result<Incoming, FrameError> {
+        loop {
+            // 只查找新读入的数据, 以及可能与之拼成 END_MARK 的末尾几个字节
+            let start = self.scanned.saturating_sub(END_MARK.len() - 1);
+            if let Some(pos) = find(&self.buffer[start..], END_MARK.as_bytes()) {
+                let pos = start + pos;
+                let frame = self.buffer[..pos].to_vec();
+                self.buffer.drain(..pos + END_MARK.len());
+                self.scanned = 0;
+                return Ok(Incoming::Request(frame));
+            }
+            self.scanned = self.buffer.len();
+
+            let limit = limit(&self.buffer);
+            if self.buffer.len() > limit {
+                return Err(FrameError::TooLarge(limit));
+            }
+
+            if !fill(&mut self.stream, &mut self.buffer).await {
+                // 对端关闭时处理已收到的不完整帧
+                if self.buffer.is_empty() {
+                    return Err(FrameError::Closed(None));
+                }
+                self.scanned = 0;
+                return Ok(Incoming::Request(std::mem::take(&mut self.buffer)));
+            }
+        }
+    }
+}
+
+pub struct NativeWriter<W> {
+    stream: W,
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> FrameWriter for NativeWriter<W> {
+    async fn write(&mut self, message: Outgoing) -> io::Result<()> {
+        match message {
+            Outgoing::Frame(frame) => write_timeout(&mut self.stream, frame.as_bytes()).await,
+            // 原生协议没有控制帧
+            Outgoing::Ping | Outgoing::Pong(_) | Outgoing::Close(_) => Ok(()),
+        }
+    }
+}
+
+// buffer 为 HTTP 握手后已读入的数据
+pub fn websocket<S: Socket>(stream: S, buffer: Vec<u8>) -> (WsReader<ReadHalf<S>>, WsWriter<WriteHalf<S>>) {
+    let (reader, writer) = tokio::io::split(stream);
+    (WsReader::new(reader, buffer), WsWriter { stream: writer })
+}
+
+// WebSocket (RFC 6455): 每条消息为一个原生协议帧, 末尾的 END_MARK 可省略
+pub struct WsReader<R> {
+    stream: R,
+    buffer: Vec<u8>,
+    // 分片消息中已收到的部分
+    message: Vec<u8>,
+}
+
+impl<R> WsReader<R> {
+    fn new(stream: R, buffer: Vec<u8>) -> Self {
+        WsReader {
+            stream,
+            buffer,
+            message: Vec::new(),
+        }
+    }
+
+    // 缓冲区中有完整的 ws 帧时取出, 返回 (fin, opcode, 去掩码后的 payload)
+    fn take_ws_frame(&mut self, limit: &FrameLimit) -> Result<Option<(bool, u8, Vec<u8>)>, FrameError> {
+        let buffer = &self.buffer;
+        if buffer.len() < 2 {
+            return Ok(None);
+        }
+        let fin = buffer[0] & 0x80 != 0;
+        let opcode = buffer[0] & 0x0f;
+        let masked = buffer[1] & 0x80 != 0;
+        let (len, header_len) = match buffer[1] & 0x7f {
+            126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+            127 if buffer.len() >= 10 => (u64::from_be_bytes(buffer[2..10].try_into().unwrap()), 10),
+            126 | 127 => return Ok(None),
+            len => (len as u64, 2),
+        };
+
+        // 客户端发出的帧必须带掩码
+        if !masked {
+            return Err(FrameError::Closed(Some(1002)));
+        }
+
+        let max_len = limit(&self.message);
+        if self.message.len() as u64 + len > max_len as u64 {
+            return Err(FrameError::TooLarge(max_len));
+        }
+
+        let len = len as usize;
+        if buffer.len() < header_len + 4 + len {
+            return Ok(None);
+        }
+        let mask: [u8; 4] = buffer[header_len..header_len + 4].try_into().unwrap();
+        let mut payload: Vec<u8> = self.buffer.drain(..header_len + 4 + len).skip(header_len + 4).collect();
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> FrameReader for WsReader<R> {
+    async fn read_frame(&mut self, limit: &FrameLimit) -> Result<Incoming, FrameError> {
+        loop {
+            let Some((fin, opcode, payload)) = self.take_ws_frame(limit)? else {
+                if !fill(&mut self.stream, &mut self.buffer).await {
+                    return Err(FrameError::Closed(None));
+                }
+                continue;
+            };
+
+            match opcode {
+                // 0 为分片消息的后续帧
+                0x0..=0x2 => {
+                    self.message.extend_from_slice(&payload);
+                    // 读到方法名后按方法的上限再检查一次
+                    let max_len = limit(&self.message);
+                    if self.message.len() > max_len {
+                        return Err(FrameError::TooLarge(max_len));
+                    }
+                    if fin {
+                        let mut message = std::mem::take(&mut self.message);
+                        if message.ends_with(END_MARK.as_bytes()) {
+                            message.truncate(message.len() - END_MARK.len());
+                        }
+                        return Ok(Incoming::Request(message));
+                    }
+                }
+                // 回复对端给出的关闭码
+                0x8 => {
+                    let code = payload.get(..2).map_or(1000, |code| u16::from_be_bytes([code[0], code[1]]));
+                    return Err(FrameError::Closed(Some(code)));
+                }
+                0x9 => return Ok(Incoming::Reply(Outgoing::Pong(payload))),
+                0xA => return Ok(Incoming::KeepAlive),
+                _ => return Err(FrameError::Closed(Some(1003))),
+            }
+        }
+    }
+}
+
+pub struct WsWriter<W> {
+    stream: W,
+}
+
+impl<W: AsyncWrite + Unpin + Send> WsWriter<W> {
+    async fn write_message(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut header = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => header.push(len as u8),
+            len if len <= u16::MAX as usize => {
+                header.push(126);
+                header.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                header.push(127);
+                header.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        write_timeout(&mut self.stream, &header).await?;
+        write_timeout(&mut self.stream, payload).await
+    }
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> FrameWriter for WsWriter<W> {
+    async fn write(&mut self, message: Outgoing) -> io::Result<()> {
+        match message {
+            Outgoing::Frame(frame) => self.write_message(0x1, frame.as_bytes()).await,
+            Outgoing::Ping => self.write_message(0x9, b"").await,
+            Outgoing::Pong(payload) => self.write_message(0xA, &payload).await,
+            Outgoing::Close(code) => self.write_message(0x8, &code.to_be_bytes()).await,
+        }
+    }
+}
+
+pub fn ws_accept_key(key: &str) -> String {
+    general_purpose::STANDARD.encode(sha1(format!("{}{}", key.trim(), WS_GUID).as_bytes()))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::ReadBuf;
+
+    use super::*;
+
+    // 每次 read 返回一段预设数据, 用完后视为对端关闭
+    struct MockStream {
+        chunks: VecDeque<Vec<u8>>,
+    }
+
+    impl MockStream {
+        fn new(chunks: &[&[u8]]) -> Self {
+            MockStream {
+                chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+            }
+        }
+    }
+
+    impl AsyncRead for MockStream {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if let Some(mut chunk) = self.chunks.pop_front() {
+                let n = chunk.len().min(buf.remaining());
+                buf.put_slice(&chunk[..n]);
+                if n < chunk.len() {
+                    self.chunks.push_front(chunk.split_off(n));
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    const NO_LIMIT: &FrameLimit = &|_| usize::MAX;
+
+    fn request(frame: &[u8]) -> Incoming {
+        Incoming::Request(frame.to_vec())
+    }
+
+    // 客户端发出的帧, 掩码固定为 [1, 2, 3, 4]
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = [1, 2, 3, 4];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    #[tokio::test]
+    async fn native_frames_across_reads() {
+        let stream = MockStream::new(&[b"login a b\n", b"\n", b"\nlogout c d\n\n", b"\n"]);
+        let mut reader = NativeReader::new(stream);
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(b"login a b"));
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(b"logout c d"));
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(None))));
+    }
+
+    #[tokio::test]
+    async fn native_incomplete_frame_at_eof() {
+        let mut reader = NativeReader::new(MockStream::new(&[b"login a b"]));
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(b"login a b"));
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(None))));
+    }
+
+    #[tokio::test]
+    async fn native_frame_too_large() {
+        let mut reader = NativeReader::new(MockStream::new(&[b"send 0123456789"]));
+        assert!(matches!(reader.read_frame(&|_| 8).await, Err(FrameError::TooLarge(8))));
+    }
+
+    #[tokio::test]
+    async fn ws_message_strips_end_mark() {
+        let stream = MockStream::new(&[&client_frame(true, 0x1, b"login a b\n\n\n")]);
+        let mut reader = WsReader::new(stream, Vec::new());
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(b"login a b"));
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(None))));
+    }
+
+    #[tokio::test]
+    async fn ws_fragmented_message_with_ping() {
+        let mut input = client_frame(false, 0x2, b"login ");
+        input.extend(client_frame(true, 0x9, b"hi"));
+        input.extend(client_frame(true, 0xA, b""));
+        input.extend(client_frame(true, 0x0, b"a b"));
+        // 帧头被拆到两次 read 中, 握手时多读的数据先被处理
+        let (head, tail) = input.split_at(3);
+        let mut reader = WsReader::new(MockStream::new(&[tail]), head.to_vec());
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), Incoming::Reply(Outgoing::Pong(b"hi".to_vec())));
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), Incoming::KeepAlive);
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(b"login a b"));
+    }
+
+    #[tokio::test]
+    async fn ws_extended_lengths() {
+        let medium = vec![b'm'; 300];
+        let large = vec![b'l'; 70_000];
+        let mut input = client_frame(true, 0x2, &medium);
+        input.extend(client_frame(true, 0x2, &large));
+        let mut reader = WsReader::new(MockStream::new(&[&input]), Vec::new());
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(&medium));
+        assert_eq!(reader.read_frame(NO_LIMIT).await.unwrap(), request(&large));
+
+        let mut writer = WsWriter { stream: Vec::new() };
+        writer.write(Outgoing::Frame("m".repeat(300))).await.unwrap();
+        writer.write(Outgoing::Frame("l".repeat(70_000))).await.unwrap();
+        let output = writer.stream;
+        assert_eq!(output[..4], [0x81, 126, 0x01, 0x2C]);
+        let large_head = &output[4 + medium.len()..4 + medium.len() + 10];
+        assert_eq!(large_head[..2], [0x81, 127]);
+        assert_eq!(u64::from_be_bytes(large_head[2..].try_into().unwrap()), 70_000);
+    }
+
+    #[tokio::test]
+    async fn ws_control_frames_written() {
+        let mut writer = WsWriter { stream: Vec::new() };
+        writer.write(Outgoing::Ping).await.unwrap();
+        writer.write(Outgoing::Pong(b"hi".to_vec())).await.unwrap();
+        writer.write(Outgoing::Close(1000)).await.unwrap();
+        assert_eq!(writer.stream, [0x89, 0, 0x8A, 2, b'h', b'i', 0x88, 2, 0x03, 0xE8]);
+
+        // 原生协议忽略控制帧
+        let mut writer = NativeWriter { stream: Vec::new() };
+        writer.write(Outgoing::Ping).await.unwrap();
+        writer.write(Outgoing::Close(1000)).await.unwrap();
+        assert!(writer.stream.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ws_unmasked_frame_rejected() {
+        let mut reader = WsReader::new(MockStream::new(&[&[0x81, 3, b'a', b'b', b'c']]), Vec::new());
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(Some(1002)))));
+    }
+
+    #[tokio::test]
+    async fn ws_close_code_returned() {
+        let stream = MockStream::new(&[&client_frame(true, 0x8, &1001u16.to_be_bytes())]);
+        let mut reader = WsReader::new(stream, Vec::new());
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(Some(1001)))));
+
+        let mut reader = WsReader::new(MockStream::new(&[&client_frame(true, 0x8, b"")]), Vec::new());
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(Some(1000)))));
+    }
+
+    #[tokio::test]
+    async fn ws_unknown_opcode_rejected() {
+        let mut reader = WsReader::new(MockStream::new(&[&client_frame(true, 0x3, b"")]), Vec::new());
+        assert!(matches!(reader.read_frame(NO_LIMIT).await, Err(FrameError::Closed(Some(1003)))));
+    }
+
+    #[tokio::test]
+    async fn ws_too_large_before_payload_arrives() {
+        let frame = client_frame(true, 0x2, &[0; 64]);
+        // 只收到帧头时就按声明的长度拒绝
+        let mut reader = WsReader::new(MockStream::new(&[&frame[..6]]), Vec::new());
+        assert!(matches!(reader.read_frame(&|_| 16).await, Err(FrameError::TooLarge(16))));
+    }
+
+    #[test]
+    fn accept_key() {
+        // RFC 6455 1.3 中的示例
+        assert_eq!(ws_accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Deserialize;
+
+use crate::{
+    config::CONFIG,
+    control_block::{ControlBlock, parse_input},
+    db::get_sql_opt,
+    engine::return_code::{ErrorCode, ReturnCode},
+    make_failed_resp, make_success_resp, scrubber,
+};
+
+fn check_admin(block: &ControlBlock) -> Result<(), String> {
+    block.check_jwt()?;
+
+    let user_name = block.user_name().map_err(|e| format!("{}: invalid jwt: {e}", ErrorCode::Unauthorized))?;
+    if !CONFIG.is_admin(&user_name) {
+        return Err(format!("{}: permission denied", ErrorCode::PermissionDenied));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ScrubReq {
+    // 0 表示不限速
+    bytes_per_sec: Option<u64>,
+}
+
+pub async fn scrub(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<ScrubReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_admin(&block) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let bytes_per_sec = req.bytes_per_sec.unwrap_or(CONFIG.scrub_bytes_per_sec);
+    let status = match scrubber::start(bytes_per_sec) {
+        Ok(status) => status,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    match serde_json::to_string(&status) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+// 查询巡检是否在运行以及最近一次的结果
+pub async fn scrub_status(payload: String) -> ReturnCode {
+    let (block, _) = match parse_input::<i32>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_admin(&block) {
+        return make_failed_resp!(payload: e);
+    }
+
+    match serde_json::to_string(&scrubber::status()) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+// 配额为空表示使用默认配额
+#[derive(Deserialize)]
+pub struct SetQuotaReq {
+    user_name: String,
+    max_bytes: Option<i64>,
+    max_files: Option<i32>,
+}
+
+pub async fn set_quota(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<SetQuotaReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_admin(&block) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let sql_opt = get_sql_opt().await;
+    match sql_opt.set_user_quota(&req.user_name, req.max_bytes, req.max_files).await {
+        Ok(_) => make_success_resp!(),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+// cert_subject 为空表示解除绑定
+#[derive(Deserialize)]
+pub struct BindCertReq {
+    user_name: String,
+    cert_subject: Option<String>,
+}
+
+pub async fn bind_cert(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<BindCertReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_admin(&block) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let sql_opt = get_sql_opt().await;
+    match sql_opt.set_cert_subject(&req.user_name, req.cert_subject.as_deref()).await {
+        Ok(true) => make_success_resp!(),
+        Ok(false) => make_failed_resp!(payload: "user not found"),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
@@ -0,0 +1,427 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG,
+    control_block::{ControlBlock, parse_input},
+    db::{Directory, FileInfo, get_sql_opt},
+    engine::return_code::{ErrorCode, ReturnCode},
+    handler::{
+        event::{self, EventKind},
+        info::check_file_owner,
+    },
+    make_failed_resp, make_success_resp,
+};
+
+pub const ROOT_DIR_ID: i32 = 0;
+
+pub enum Node {
+    Dir(i32),
+    File(FileInfo),
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(format!("invalid name: {name:?}"));
+    }
+    if name.len() > 255 {
+        return Err("name too long".to_string());
+    }
+    Ok(())
+}
+
+fn split_path(path: &str) -> Result<Vec<&str>, String> {
+    let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+    for part in &parts {
+        validate_name(part)?;
+    }
+    Ok(parts)
+}
+
+// 拆出父目录路径与最后一段名字, 如 /projects/a/report.pdf -> (["projects", "a"], "report.pdf")
+fn split_parent(path: &str) -> Result<(Vec<&str>, &str), String> {
+    let mut parts = split_path(path)?;
+    match parts.pop() {
+        Some(name) => Ok((parts, name)),
+        None => Err("path refers to root directory".to_string()),
+    }
+}
+
+async fn walk_dirs(parts: &[&str]) -> Result<i32, String> {
+    let sql_opt = get_sql_opt().await;
+    let mut dir_id = ROOT_DIR_ID;
+    for part in parts {
+        match sql_opt.get_directory(dir_id, part).await {
+            Ok(Some(dir)) => dir_id = dir.id,
+            Ok(None) => return Err(format!("directory not found: {part}")),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(dir_id)
+}
+
+pub async fn resolve_dir(path: &str) -> Result<i32, String> {
+    walk_dirs(&split_path(path)?).await
+}
+
+pub async fn resolve(path: &str) -> Result<Node, String> {
+    let parts = split_path(path)?;
+    let Some((name, parents)) = parts.split_last() else {
+        return Ok(Node::Dir(ROOT_DIR_ID));
+    };
+
+    let parent_id = walk_dirs(parents).await?;
+    let sql_opt = get_sql_opt().await;
+    if let Some(dir) = sql_opt.get_directory(parent_id, name).await.map_err(|e| e.to_string())? {
+        return Ok(Node::Dir(dir.id));
+    }
+    match sql_opt.get_dir_file(parent_id, name).await.map_err(|e| e.to_string())? {
+        Some(file) => Ok(Node::File(file)),
+        None => Err(format!("no such file or directory: {path}")),
+    }
+}
+
+// 同一目录下文件与子目录不能重名
+pub async fn check_name_free(dir_id: i32, name: &str) -> Result<(), String> {
+    let sql_opt = get_sql_opt().await;
+    let dir = sql_opt.get_directory(dir_id, name).await.map_err(|e| e.to_string())?;
+    let file = sql_opt.get_dir_file(dir_id, name).await.map_err(|e| e.to_string())?;
+    if dir.is_some() || file.is_some() {
+        return Err(format!("name already exists: {name}"));
+    }
+    Ok(())
+}
+
+async fn is_descendant(dir_id: i32, ancestor_id: i32) -> Result<bool, String> {
+    let sql_opt = get_sql_opt().await;
+    let mut cur = dir_id;
+    while cur != ROOT_DIR_ID {
+        if cur == ancestor_id {
+            return Ok(true);
+        }
+        cur = sql_opt.get_directory_by_id(cur).await.map_err(|e| e.to_string())?.parent_id;
+    }
+    Ok(false)
+}
+
+// 广度优先收集整棵子树
+async fn collect_subtree(dir_id: i32) -> Result<Vec<i32>, String> {
+    let sql_opt = get_sql_opt().await;
+    let mut dir_ids = vec![dir_id];
+    let mut i = 0;
+    while i < dir_ids.len() {
+        let dirs = sql_opt.get_sub_directories(dir_ids[i]).await.map_err(|e| e.to_string())?;
+        dir_ids.extend(dirs.into_iter().map(|dir| dir.id));
+        i += 1;
+    }
+    Ok(dir_ids)
+}
+
+// 目录没有所有者, 移动或删除目录要求调用者是管理员或拥有子树中的全部文件
+// 与 delete_file 一致, 没有所有者的旧文件不限制
+async fn check_subtree_owner(block: &ControlBlock, dir_ids: &[i32]) -> Result<String, String> {
+    block.check_jwt()?;
+    let user_name = block.user_name().map_err(|e| format!("{}: invalid jwt: {e}", ErrorCode::Unauthorized))?;
+    if CONFIG.is_admin(&user_name) {
+        return Ok(user_name);
+    }
+
+    let sql_opt = get_sql_opt().await;
+    for dir_id in dir_ids {
+        let owners = sql_opt.get_dir_owners(*dir_id).await.map_err(|e| e.to_string())?;
+        if owners.iter().flatten().any(|owner| *owner != user_name) {
+            return Err(format!("{}: permission denied", ErrorCode::PermissionDenied));
+        }
+    }
+    Ok(user_name)
+}
+
+async fn check_node_owner(block: &ControlBlock, node: &Node) -> Result<(), String> {
+    match node {
+        Node::File(file) => check_file_owner(block, file).map(|_| ()),
+        // 根目录不能移动, 由 move_node 报错
+        Node::Dir(ROOT_DIR_ID) => block.check_jwt(),
+        Node::Dir(id) => check_subtree_owner(block, &collect_subtree(*id).await?).await.map(|_| ()),
+    }
+}
+
+// 检查与更新之间的并发冲突由数据库唯一键兜底
+pub fn name_error(e: sqlx::Error, name: &str) -> String {
+    match e.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => format!("name already exists: {name}"),
+        _ => e.to_string(),
+    }
+}
+
+async fn move_node(node: Node, dst_dir_id: i32, new_name: &str) -> Result<(), String> {
+    validate_name(new_name)?;
+
+    let sql_opt = get_sql_opt().await;
+    match node {
+        Node::Dir(ROOT_DIR_ID) => Err("cannot move root directory".to_string()),
+        Node::Dir(id) => {
+            let dir = sql_opt.get_directory_by_id(id).await.map_err(|e| e.to_string())?;
+            // 移动到原位置时无需操作
+            if dir.parent_id == dst_dir_id && dir.dir_name == new_name {
+                return Ok(());
+            }
+            check_name_free(dst_dir_id, new_name).await?;
+            if is_descendant(dst_dir_id, id).await? {
+                return Err("cannot move a directory into itself".to_string());
+            }
+            sql_opt.move_directory(id, dst_dir_id, new_name).await.map_err(|e| name_error(e, new_name))
+        }
+        Node::File(file) => {
+            if file.dir_id == dst_dir_id && file.file_name == new_name {
+                return Ok(());
+            }
+            check_name_free(dst_dir_id, new_name).await?;
+            sql_opt.move_file(file.id, dst_dir_id, new_name).await.map_err(|e| name_error(e, new_name))?;
+            event::publish_file(&file, EventKind::FileRenamed {
+                file_id: file.id,
+                dir_id: dst_dir_id,
+                old_name: file.file_name.clone(),
+                new_name: new_name.to_string(),
+            });
+            Ok(())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MkdirReq {
+    path: String,
+    #[serde(default)]
+    parents: bool,
+}
+
+pub async fn mkdir(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<MkdirReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let (parents, name) = match split_parent(&req.path) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let mut parent_id = ROOT_DIR_ID;
+    for part in parents {
+        match sql_opt.get_directory(parent_id, part).await {
+            Ok(Some(dir)) => parent_id = dir.id,
+            Ok(None) if req.parents => {
+                if let Err(e) = check_name_free(parent_id, part).await {
+                    return make_failed_resp!(payload: e);
+                }
+                parent_id = match sql_opt.create_directory(parent_id, part).await {
+                    Ok(id) => id,
+                    Err(e) => return make_failed_resp!(payload: name_error(e, part)),
+                };
+            }
+            Ok(None) => return make_failed_resp!(payload: format!("directory not found: {part}")),
+            Err(e) => return make_failed_resp!(payload: e),
+        }
+    }
+
+    if let Err(e) = check_name_free(parent_id, name).await {
+        return make_failed_resp!(payload: e);
+    }
+
+    match sql_opt.create_directory(parent_id, name).await {
+        Ok(id) => make_success_resp!(payload: id),
+        Err(e) => make_failed_resp!(payload: name_error(e, name)),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListDirReq {
+    path: String,
+}
+
+#[derive(Serialize)]
+pub struct ListDirResp {
+    dir_id: i32,
+    dirs: Vec<Directory>,
+    files: Vec<FileInfo>,
+}
+
+pub async fn list_dir(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<ListDirReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let dir_id = match resolve_dir(&req.path).await {
+        Ok(id) => id,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let dirs = match sql_opt.get_sub_directories(dir_id).await {
+        Ok(dirs) => dirs,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+    let files = match sql_opt.get_dir_files(dir_id).await {
+        Ok(files) => files,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let resp = ListDirResp {
+        dir_id,
+        dirs,
+        files,
+    };
+
+    match serde_json::to_string(&resp) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MoveReq {
+    src_path: String,
+    dst_dir: String,
+}
+
+pub async fn move_path(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<MoveReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let name = match split_parent(&req.src_path) {
+        Ok((_, name)) => name.to_string(),
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let node = match resolve(&req.src_path).await {
+        Ok(node) => node,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_node_owner(&block, &node).await {
+        return make_failed_resp!(payload: e);
+    }
+
+    let dst_dir_id = match resolve_dir(&req.dst_dir).await {
+        Ok(id) => id,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    match move_node(node, dst_dir_id, &name).await {
+        Ok(_) => make_success_resp!(),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenameReq {
+    path: String,
+    new_name: String,
+}
+
+pub async fn rename(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<RenameReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let parent_id = match split_parent(&req.path) {
+        Ok((parents, _)) => match walk_dirs(&parents).await {
+            Ok(id) => id,
+            Err(e) => return make_failed_resp!(payload: e),
+        },
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let node = match resolve(&req.path).await {
+        Ok(node) => node,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_node_owner(&block, &node).await {
+        return make_failed_resp!(payload: e);
+    }
+
+    match move_node(node, parent_id, &req.new_name).await {
+        Ok(_) => make_success_resp!(),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RmdirReq {
+    path: String,
+    #[serde(default)]
+    recursive: bool,
+}
+
+pub async fn rmdir(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<RmdirReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let dir_id = match resolve_dir(&req.path).await {
+        Ok(ROOT_DIR_ID) => return make_failed_resp!(payload: "cannot remove root directory"),
+        Ok(id) => id,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let dir_ids = match collect_subtree(dir_id).await {
+        Ok(dir_ids) => dir_ids,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let user_name = match check_subtree_owner(&block, &dir_ids).await {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+
+    if !req.recursive {
+        let files = match sql_opt.get_dir_files(dir_id).await {
+            Ok(files) => files,
+            Err(e) => return make_failed_resp!(payload: e),
+        };
+        if dir_ids.len() > 1 || !files.is_empty() {
+            return make_failed_resp!(payload: "directory not empty");
+        }
+    }
+
+    match sql_opt.delete_directories(&dir_ids, &user_name).await {
+        Ok(files) => {
+            for file in &files {
+                event::publish_file(file, EventKind::FileDeleted {
+                    file_id: file.id,
+                    file_name: file.file_name.clone(),
+                });
+            }
+            make_success_resp!()
+        }
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
@@ -1,8 +1,7 @@
-use std::io::Read;
-
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 
-use crate::{control_block::parse_input, db::{get_sql_opt, FileBlock}, engine::return_code::ReturnCode, make_failed_resp, make_success_resp};
+use crate::{control_block::parse_input, db::{get_sql_opt, FileBlock}, engine::return_code::ReturnCode, make_failed_resp, make_success_resp, storage, utils::{checksum, CRC32}};
 
 #[derive(Deserialize)]
 pub struct GetBlockIdsByFileIdReq {
@@ -35,6 +34,78 @@ pub async fn get_block_ids_by_file_id(payload: String) -> ReturnCode {
     make_success_resp!(payload: resp)
 }
 
+#[derive(Deserialize)]
+pub struct GetManifestReq {
+    file_id: i32,
+}
+
+#[derive(Serialize)]
+pub struct BlockManifest {
+    id: i32,
+    block_id: i64,
+    offset: u64,
+    block_size: u32,
+    block_checksum: u32,
+}
+
+#[derive(Serialize)]
+pub struct GetManifestResp {
+    file_id: i32,
+    file_name: String,
+    file_size: i64,
+    file_checksum: u32,
+    blocks: Vec<BlockManifest>,
+}
+
+pub async fn get_manifest(payload: String) -> ReturnCode {
+    let (_, req) = match parse_input::<GetManifestReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if !file_info.is_readable() {
+        return make_failed_resp!(payload: "file not available");
+    }
+
+    let blocks = match sql_opt.get_blocks_by_file_id(req.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let mut offset = 0u64;
+    let blocks = blocks
+        .into_iter()
+        .map(|block| {
+            let manifest = BlockManifest {
+                id: block.id,
+                block_id: block.block_id,
+                offset,
+                block_size: block.block_size,
+                block_checksum: block.block_checksum,
+            };
+            offset += block.block_size as u64;
+            manifest
+        })
+        .collect();
+
+    let resp = GetManifestResp {
+        file_id: file_info.id,
+        file_name: file_info.file_name,
+        file_size: file_info.file_size,
+        file_checksum: file_info.file_checksum,
+        blocks,
+    };
+
+    let resp = serde_json::to_string(&resp).unwrap();
+
+    make_success_resp!(payload: resp)
+}
 
 #[derive(Deserialize)]
 pub struct GetBlockReq {
@@ -59,11 +130,20 @@ pub async fn get_block(payload: String) -> ReturnCode {
         Err(e) => return make_failed_resp!(payload: e),
     };
 
-    let mut fd = std::fs::File::open(&block_info.block_name).unwrap();
-    let mut data: Vec<u8> = Vec::new();
-    if let Err(e) = fd.read_to_end(&mut data) {
-        return make_failed_resp!(payload: e)
-    }
+    let file_info = match sql_opt.get_file_info_by_id(block_info.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let data_key = match storage::file_data_key(&file_info) {
+        Ok(key) => key,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let data = match storage::read_block(&block_info, data_key.as_ref()).await {
+        Ok(data) => data,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
 
     let resp = GetBlockResp {
         block_info,
@@ -74,3 +154,194 @@ pub async fn get_block(payload: String) -> ReturnCode {
 
     make_success_resp!(payload: resp)
 }
+
+
+const MAX_RANGE_LENGTH: u64 = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct ReadRangeReq {
+    file_id: i32,
+    offset: u64,
+    length: u64,
+}
+
+#[derive(Serialize)]
+pub struct ReadRangeResp {
+    file_id: i32,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+pub async fn read_range(payload: String) -> ReturnCode {
+    let (_, req) = match parse_input::<ReadRangeReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if req.length > MAX_RANGE_LENGTH {
+        return make_failed_resp!(payload: format!("range length exceeds {MAX_RANGE_LENGTH} bytes"));
+    }
+
+    let sql_opt = get_sql_opt().await;
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if !file_info.is_readable() {
+        return make_failed_resp!(payload: "file not available");
+    }
+
+    let data_key = match storage::file_data_key(&file_info) {
+        Ok(key) => key,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let blocks = match sql_opt.get_blocks_by_file_id(req.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let range_end = req.offset.saturating_add(req.length);
+    let mut data = Vec::new();
+    let mut block_start = 0u64;
+    for block in blocks {
+        let block_end = block_start + block.block_size as u64;
+        if block_end <= req.offset {
+            block_start = block_end;
+            continue;
+        }
+        if block_start >= range_end {
+            break;
+        }
+
+        let block_data = match storage::read_block(&block, data_key.as_ref()).await {
+            Ok(data) => data,
+            Err(e) => return make_failed_resp!(payload: e),
+        };
+
+        let from = req.offset.saturating_sub(block_start) as usize;
+        let to = (range_end.min(block_end) - block_start) as usize;
+        if to > block_data.len() {
+            return make_failed_resp!(payload: format!("block {} is shorter than recorded", block.id));
+        }
+        data.extend_from_slice(&block_data[from..to]);
+
+        block_start = block_end;
+    }
+
+    let resp = ReadRangeResp {
+        file_id: req.file_id,
+        offset: req.offset,
+        data,
+    };
+
+    let resp = serde_json::to_string(&resp).unwrap();
+
+    make_success_resp!(payload: resp)
+}
+
+#[derive(Deserialize)]
+pub struct DownloadFileReq {
+    file_id: i32,
+}
+
+// 首帧为 header, 随后每个块一帧, 最后以 end 帧结束
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DownloadFrame {
+    Header {
+        file_id: i32,
+        file_name: String,
+        file_size: i64,
+        block_count: usize,
+    },
+    Block {
+        block_id: i64,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    End {
+        total_size: u64,
+        file_checksum: u32,
+        stream_checksum: u32,
+    },
+}
+
+pub async fn download_file(payload: String) -> ReturnCode {
+    let (_, req) = match parse_input::<DownloadFileReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if !file_info.is_readable() {
+        return make_failed_resp!(payload: "file not available");
+    }
+
+    let data_key = match storage::file_data_key(&file_info) {
+        Ok(key) => key,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let blocks = match sql_opt.get_blocks_by_file_id(req.file_id).await {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let header = DownloadFrame::Header {
+        file_id: file_info.id,
+        file_name: file_info.file_name.clone(),
+        file_size: file_info.file_size,
+        block_count: blocks.len(),
+    };
+    let header = serde_json::to_string(&header).unwrap();
+
+    let (tx, rx) = mpsc::channel(4);
+    let file_checksum = file_info.file_checksum;
+    tokio::spawn(async move {
+        let mut digest = CRC32.digest();
+        let mut offset = 0u64;
+        for block in blocks {
+            let data = match storage::read_block(&block, data_key.as_ref()).await {
+                Ok(data) => data,
+                Err(e) => {
+                    let _ = tx.send(make_failed_resp!(payload: e)).await;
+                    return;
+                }
+            };
+
+            if checksum(&data) != block.block_checksum {
+                let _ = tx.send(make_failed_resp!(payload: format!("block {} checksum mismatch", block.block_id))).await;
+                return;
+            }
+
+            digest.update(&data);
+            let n = data.len() as u64;
+            let frame = DownloadFrame::Block {
+                block_id: block.block_id,
+                offset,
+                data,
+            };
+            offset += n;
+
+            if tx.send(make_success_resp!(payload: serde_json::to_string(&frame).unwrap())).await.is_err() {
+                return;
+            }
+        }
+
+        let end = DownloadFrame::End {
+            total_size: offset,
+            file_checksum,
+            stream_checksum: digest.finalize(),
+        };
+        let _ = tx.send(make_success_resp!(payload: serde_json::to_string(&end).unwrap())).await;
+    });
+
+    make_success_resp!(payload: header, stream: rx)
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use dashmap::DashSet;
+use lazy_static::lazy_static;
+use log::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    config::CONFIG,
+    control_block::parse_input,
+    db::FileInfo,
+    engine::return_code::{ErrorCode, ReturnCode},
+    handler::quota,
+    make_failed_resp, make_success_resp,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    FileCompleted { file_id: i32, file_name: String, file_size: i64 },
+    FileDeleted { file_id: i32, file_name: String },
+    FileRenamed { file_id: i32, dir_id: i32, old_name: String, new_name: String },
+    FileRestored { file_id: i32, dir_id: i32 },
+    FilePurged { file_id: i32 },
+    QuotaWarning { used_bytes: i64, max_bytes: Option<i64>, file_count: i32, max_files: Option<i32> },
+}
+
+#[derive(Serialize, Debug)]
+pub struct Event {
+    seq: u64,
+    #[serde(skip)]
+    user_name: String,
+    time: NaiveDateTime,
+    #[serde(flatten)]
+    kind: EventKind,
+}
+
+// 广播新事件, 同时保留最近的事件供断线后按 seq 续订
+struct EventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+    // (下一个 seq, 最近的事件)
+    history: Mutex<(u64, VecDeque<Arc<Event>>)>,
+}
+
+lazy_static! {
+    static ref EVENT_BUS: EventBus = EventBus {
+        sender: broadcast::channel(CONFIG.event_history.max(1)).0,
+        history: Mutex::new((1, VecDeque::new())),
+    };
+    // 已提醒过配额的用户, 用量回落到阈值以下后移除
+    static ref QUOTA_WARNED: DashSet<String> = DashSet::new();
+}
+
+pub fn publish(user_name: &str, kind: EventKind) {
+    let mut history = EVENT_BUS.history.lock().unwrap();
+    let event = Arc::new(Event {
+        seq: history.0,
+        user_name: user_name.to_string(),
+        time: Utc::now().naive_utc(),
+        kind,
+    });
+    history.0 += 1;
+    trace!("event: {:?}", event);
+
+    history.1.push_back(Arc::clone(&event));
+    if history.1.len() > CONFIG.event_history {
+        history.1.pop_front();
+    }
+    drop(history);
+
+    // 释放锁后再广播, 订阅端按历史快照的 seq 去重; 没有订阅者时发送失败可忽略
+    let _ = EVENT_BUS.sender.send(event);
+}
+
+// 事件发给文件所有者, 没有所有者的旧文件不产生事件
+pub fn publish_file(file_info: &FileInfo, kind: EventKind) {
+    if let Some(owner) = &file_info.owner {
+        publish(owner, kind);
+    }
+}
+
+// 用量达到配额的 QUOTA_WARN_PERCENT 时提醒用户, 每次越过阈值只提醒一次
+pub async fn check_quota_warning(user_name: &str) {
+    if CONFIG.quota_warn_percent == 0 {
+        return;
+    }
+
+    let usage = match quota::load_usage(user_name).await {
+        Ok(usage) => usage,
+        Err(e) => {
+            warn!("failed to load usage of {}: {}", user_name, e);
+            return;
+        }
+    };
+
+    let percent = CONFIG.quota_warn_percent as i64;
+    let near_limit = usage.max_bytes.is_some_and(|max| usage.used_bytes * 100 >= max * percent)
+        || usage.max_files.is_some_and(|max| usage.file_count as i64 * 100 >= max as i64 * percent);
+    if !near_limit {
+        QUOTA_WARNED.remove(user_name);
+        return;
+    }
+    if QUOTA_WARNED.insert(user_name.to_string()) {
+        publish(user_name, EventKind::QuotaWarning {
+            used_bytes: usage.used_bytes,
+            max_bytes: usage.max_bytes,
+            file_count: usage.file_count,
+            max_files: usage.max_files,
+        });
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SubscribeReq {
+    // 上次收到的事件 seq, 续订时先补发之后的事件
+    after_seq: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SubscribeResp {
+    // 当前最新事件的 seq
+    last_seq: u64,
+    // after_seq 之后的部分事件已不在历史中, 客户端需要重新 list_file 同步
+    missed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamFrame {
+    Heartbeat { last_seq: u64 },
+}
+
+// 首个响应之后以流式响应推送调用者的事件, 直到连接关闭
+pub async fn subscribe(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<SubscribeReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let user_name = match block.user_name() {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}")),
+    };
+
+    let (mut receiver, replay, resp) = {
+        let history = EVENT_BUS.history.lock().unwrap();
+        let last_seq = history.0 - 1;
+        let after_seq = req.after_seq.unwrap_or(last_seq);
+        let oldest = history.1.front().map_or(history.0, |event| event.seq);
+        let replay: Vec<Arc<Event>> = history
+            .1
+            .iter()
+            .filter(|event| event.seq > after_seq && event.user_name == user_name)
+            .cloned()
+            .collect();
+        let resp = SubscribeResp {
+            last_seq,
+            // 服务重启后 seq 重新计数
+            missed: after_seq.saturating_add(1) < oldest || after_seq > last_seq,
+        };
+        (EVENT_BUS.sender.subscribe(), replay, resp)
+    };
+
+    // 快照之前的事件已在历史中, 释放锁后才广播的可能再次收到
+    let snapshot_seq = resp.last_seq;
+    let mut last_seq = resp.last_seq;
+    let resp = match serde_json::to_string(&resp) {
+        Ok(resp) => resp,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        for event in replay {
+            if tx.send(make_success_resp!(payload: serde_json::to_string(&*event).unwrap())).await.is_err() {
+                return;
+            }
+        }
+
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        heartbeat.tick().await;
+        loop {
+            let event = tokio::select! {
+                event = receiver.recv() => event,
+                _ = heartbeat.tick() => {
+                    // 定期发送心跳, 及时发现已断开的连接
+                    let frame = StreamFrame::Heartbeat { last_seq };
+                    if tx.send(make_success_resp!(payload: serde_json::to_string(&frame).unwrap())).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            match event {
+                Ok(event) => {
+                    // 并发发布时广播顺序可能与 seq 不一致
+                    last_seq = last_seq.max(event.seq);
+                    if event.seq <= snapshot_seq || event.user_name != user_name {
+                        continue;
+                    }
+                    if tx.send(make_success_resp!(payload: serde_json::to_string(&*event).unwrap())).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("subscriber {} lagged by {} events", user_name, n);
+                    let _ = tx
+                        .send(make_failed_resp!(payload: format!("subscriber lagged, resubscribe with after_seq {last_seq}")))
+                        .await;
+                    return;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+
+    make_success_resp!(payload: resp, stream: rx)
+}
@@ -1,15 +1,88 @@
+use std::collections::BTreeMap;
+
+use base64::{Engine, engine::general_purpose};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
-use crate::{control_block::parse_input, db::{get_sql_opt, FileInfo}, engine::return_code::ReturnCode, make_failed_resp, make_success_resp};
+use crate::{config::CONFIG, control_block::{parse_input, ControlBlock}, db::{get_sql_opt, FileInfo, FileQuery, SortKey, SortValue}, engine::return_code::{ErrorCode, ReturnCode}, handler::{dir, event::{self, EventKind}}, make_failed_resp, make_success_resp};
 
-#[derive(Deserialize)]
+const MAX_TAGS_PER_REQ: usize = 64;
+
+// 文件所有者或管理员才能修改文件, 没有所有者的旧文件只有管理员能修改
+pub fn check_file_owner(block: &ControlBlock, file_info: &FileInfo) -> Result<String, String> {
+    block.check_jwt()?;
+    let user_name = block.user_name().map_err(|e| format!("{}: invalid jwt: {e}", ErrorCode::Unauthorized))?;
+
+    if file_info.owner.as_deref() == Some(user_name.as_str()) || CONFIG.is_admin(&user_name) {
+        Ok(user_name)
+    } else {
+        Err(format!("{}: permission denied", ErrorCode::PermissionDenied))
+    }
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1000;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
 pub struct ListFileReq {
-    filter: String
+    filter: String,
+    name_prefix: Option<String>,
+    // 支持 * 与 ? 通配符
+    name_glob: Option<String>,
+    min_size: Option<i64>,
+    max_size: Option<i64>,
+    created_after: Option<NaiveDateTime>,
+    created_before: Option<NaiveDateTime>,
+    sort: SortKey,
+    desc: bool,
+    limit: u32,
+    cursor: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct ListFileResp {
     file_info: Vec<FileInfo>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ListCursor {
+    last: SortValue,
+    id: i32,
+    desc: bool,
+}
+
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn glob_to_like(glob: &str) -> String {
+    glob.chars()
+        .map(|c| match c {
+            '*' => "%".to_string(),
+            '?' => "_".to_string(),
+            c => escape_like(&c.to_string()),
+        })
+        .collect()
+}
+
+fn encode_cursor(cursor: &ListCursor) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_string(cursor).unwrap())
+}
+
+fn decode_cursor(cursor: &str, sort: SortKey, desc: bool) -> Result<ListCursor, String> {
+    let data = general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|e| format!("invalid cursor: {e}"))?;
+    let cursor: ListCursor = serde_json::from_slice(&data).map_err(|e| format!("invalid cursor: {e}"))?;
+    if cursor.last.key() != sort {
+        return Err("cursor does not match sort key".to_string());
+    }
+    if cursor.desc != desc {
+        return Err("cursor does not match sort order".to_string());
+    }
+    Ok(cursor)
 }
 
 pub async fn list_file(payload: String) -> ReturnCode {
@@ -18,31 +91,64 @@ pub async fn list_file(payload: String) -> ReturnCode {
         Err(e) => return make_failed_resp!(payload: e),
     };
 
-    let sql_opt = get_sql_opt().await;
+    let limit = match req.limit {
+        0 => DEFAULT_PAGE_SIZE,
+        n => n.min(MAX_PAGE_SIZE),
+    };
 
-    let ids = match sql_opt.get_file_ids().await {
-        Ok(ids) => ids,
-        Err(e) => return make_failed_resp!(payload: e)
+    let after = match &req.cursor {
+        Some(cursor) => match decode_cursor(cursor, req.sort, req.desc) {
+            Ok(cursor) => Some((cursor.last, cursor.id)),
+            Err(e) => return make_failed_resp!(payload: e),
+        },
+        None => None,
     };
 
-    let mut file_info_list = vec![];
-    for id in ids {
-        let file_info = match sql_opt.get_file_info_by_id(id).await {
-            Ok(file_info) => file_info,
-            Err(e) => return make_failed_resp!(payload: e)
-        };
+    let mut name_patterns = vec![];
+    if !req.filter.is_empty() {
+        name_patterns.push(format!("%{}%", escape_like(&req.filter)));
+    }
+    if let Some(prefix) = &req.name_prefix {
+        name_patterns.push(format!("{}%", escape_like(prefix)));
+    }
+    if let Some(glob) = &req.name_glob {
+        name_patterns.push(glob_to_like(glob));
+    }
 
-        if file_info.file_status != 1 {
-            continue;
-        }
+    let query = FileQuery {
+        name_patterns,
+        min_size: req.min_size,
+        max_size: req.max_size,
+        created_after: req.created_after,
+        created_before: req.created_before,
+        sort: req.sort,
+        desc: req.desc,
+        after,
+        // 多取一条判断是否还有下一页
+        limit: limit + 1,
+    };
 
-        if file_info.file_name.contains(&req.filter) {
-            file_info_list.push(file_info);
-        }
-    }
+    let sql_opt = get_sql_opt().await;
+
+    let mut file_info_list = match sql_opt.list_files(&query).await {
+        Ok(list) => list,
+        Err(e) => return make_failed_resp!(payload: e)
+    };
+
+    let next_cursor = if file_info_list.len() > limit as usize {
+        file_info_list.truncate(limit as usize);
+        file_info_list.last().map(|last| encode_cursor(&ListCursor {
+            last: SortValue::of(req.sort, last),
+            id: last.id,
+            desc: req.desc,
+        }))
+    } else {
+        None
+    };
 
     let resp = ListFileResp {
         file_info: file_info_list,
+        next_cursor,
     };
 
     let resp = match serde_json::to_string(&resp) {
@@ -77,8 +183,33 @@ pub async fn delete_file(payload: String) -> ReturnCode {
 
     let sql_opt = get_sql_opt().await;
 
-    match sql_opt.delete_file_info(req.file_id).await {
-        Ok(_) => make_success_resp!(),
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e)
+    };
+
+    if !file_info.is_mutable() {
+        return make_failed_resp!(payload: "file deleted or not the current version");
+    }
+
+    // 没有所有者的旧文件保持原有行为, 任何登录用户都可以删除
+    let user_name = match &file_info.owner {
+        Some(_) => check_file_owner(&block, &file_info),
+        None => block.user_name().map_err(|e| format!("{}: invalid jwt: {e}", ErrorCode::Unauthorized)),
+    };
+    let user_name = match user_name {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(payload: e)
+    };
+
+    match sql_opt.delete_file_info(req.file_id, &user_name).await {
+        Ok(_) => {
+            event::publish_file(&file_info, EventKind::FileDeleted {
+                file_id: file_info.id,
+                file_name: file_info.file_name.clone(),
+            });
+            make_success_resp!()
+        }
         Err(e) => make_failed_resp!(payload: e)
     }
 }
@@ -96,15 +227,183 @@ pub async fn get_file_info(payload: String) -> ReturnCode {
 
     let sql_opt = get_sql_opt().await;
 
-    match sql_opt.get_file_info_by_id(req.file_id).await {
-        Ok(file_info) => {
-            let resp = match serde_json::to_string(&file_info) {
-                Ok(resp) => resp,
-                Err(e) => return make_failed_resp!(payload: e)
-            };
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e)
+    };
 
-            make_success_resp!(payload: resp)
-        },
+    let tags = match sql_opt.get_file_tags(req.file_id).await {
+        Ok(tags) => tags.into_iter().collect(),
+        Err(e) => return make_failed_resp!(payload: e)
+    };
+
+    let resp = GetFileInfoResp {
+        file_info,
+        tags,
+    };
+
+    match serde_json::to_string(&resp) {
+        Ok(resp) => make_success_resp!(payload: resp),
         Err(e) => make_failed_resp!(payload: e)
     }
-}
\ No newline at end of file
+}
+
+#[derive(Serialize)]
+pub struct GetFileInfoResp {
+    #[serde(flatten)]
+    file_info: FileInfo,
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct RenameFileReq {
+    file_id: i32,
+    new_name: String,
+}
+
+pub async fn rename_file(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<RenameFileReq>(&payload) {
+        Ok((block, req)) => (block, req),
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e)
+    };
+
+    if !file_info.is_mutable() {
+        return make_failed_resp!(payload: "file deleted or not the current version");
+    }
+
+    if let Err(e) = check_file_owner(&block, &file_info) {
+        return make_failed_resp!(payload: e);
+    }
+
+    if file_info.file_name == req.new_name {
+        return make_success_resp!();
+    }
+
+    if let Err(e) = dir::validate_name(&req.new_name) {
+        return make_failed_resp!(payload: e);
+    }
+
+    if let Err(e) = dir::check_name_free(file_info.dir_id, &req.new_name).await {
+        return make_failed_resp!(payload: e);
+    }
+
+    match sql_opt.move_file(file_info.id, file_info.dir_id, &req.new_name).await {
+        Ok(_) => {
+            event::publish_file(&file_info, EventKind::FileRenamed {
+                file_id: file_info.id,
+                dir_id: file_info.dir_id,
+                old_name: file_info.file_name.clone(),
+                new_name: req.new_name,
+            });
+            make_success_resp!()
+        }
+        Err(e) => make_failed_resp!(payload: e)
+    }
+}
+
+// 字段为空表示保持不变, 空字符串表示清除
+#[derive(Deserialize)]
+pub struct UpdateFileMetaReq {
+    file_id: i32,
+    mime_type: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    #[serde(default)]
+    remove_tags: Vec<String>,
+}
+
+pub async fn update_file_meta(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<UpdateFileMetaReq>(&payload) {
+        Ok((block, req)) => (block, req),
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if req.tags.len() + req.remove_tags.len() > MAX_TAGS_PER_REQ {
+        return make_failed_resp!(payload: format!("at most {MAX_TAGS_PER_REQ} tags per request"));
+    }
+
+    for (key, value) in &req.tags {
+        if key.is_empty() || key.len() > 255 || value.len() > 255 {
+            return make_failed_resp!(payload: format!("invalid tag: {key:?}"));
+        }
+    }
+
+    if req.mime_type.as_ref().is_some_and(|mime_type| mime_type.len() > 255) {
+        return make_failed_resp!(payload: "mime_type too long");
+    }
+
+    let sql_opt = get_sql_opt().await;
+
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e)
+    };
+
+    if !file_info.is_mutable() {
+        return make_failed_resp!(payload: "file deleted or not the current version");
+    }
+
+    if let Err(e) = check_file_owner(&block, &file_info) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let mime_type = req.mime_type.or(file_info.mime_type).filter(|s| !s.is_empty());
+    let description = req.description.or(file_info.description).filter(|s| !s.is_empty());
+    let set_tags: Vec<(String, String)> = req.tags.into_iter().collect();
+
+    match sql_opt
+        .update_file_meta(file_info.id, mime_type.as_deref(), description.as_deref(), &set_tags, &req.remove_tags)
+        .await
+    {
+        Ok(_) => make_success_resp!(),
+        Err(e) => make_failed_resp!(payload: e)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+        assert_eq!(escape_like("report"), "report");
+    }
+
+    #[test]
+    fn glob_maps_wildcards() {
+        assert_eq!(glob_to_like("*.pdf"), "%.pdf");
+        assert_eq!(glob_to_like("v?_100%"), "v_\\_100\\%");
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = encode_cursor(&ListCursor {
+            last: SortValue::Size(1024),
+            id: 7,
+            desc: true,
+        });
+        let decoded = decode_cursor(&cursor, SortKey::Size, true).unwrap();
+        assert!(matches!(decoded.last, SortValue::Size(1024)));
+        assert_eq!(decoded.id, 7);
+    }
+
+    #[test]
+    fn cursor_rejects_other_sort_or_order() {
+        let cursor = encode_cursor(&ListCursor {
+            last: SortValue::Name("a.txt".to_string()),
+            id: 7,
+            desc: false,
+        });
+        assert!(decode_cursor(&cursor, SortKey::Size, false).is_err());
+        assert!(decode_cursor(&cursor, SortKey::Name, true).is_err());
+        assert!(decode_cursor("not a cursor", SortKey::Name, false).is_err());
+    }
+}
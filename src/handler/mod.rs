@@ -1,4 +1,13 @@
 pub mod upload;
 pub mod user;
 pub mod info;
-pub mod download;
\ No newline at end of file
+pub mod download;
+pub mod admin;
+pub mod dir;
+pub mod version;
+pub mod trash;
+pub mod search;
+pub mod quota;
+pub mod rest;
+pub mod event;
+
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{
+    config::CONFIG,
+    control_block::parse_input,
+    db::get_sql_opt,
+    engine::return_code::{ErrorCode, ReturnCode},
+    make_failed_resp, make_success_resp,
+};
+
+// max_bytes / max_files 为空表示不限制
+#[derive(Serialize)]
+pub struct Usage {
+    user_name: String,
+    pub used_bytes: i64,
+    pub file_count: i32,
+    pub max_bytes: Option<i64>,
+    pub max_files: Option<i32>,
+}
+
+// 用户未单独设置时使用默认配额, 默认配额为 0 表示不限制
+pub async fn load_usage(user_name: &str) -> Result<Usage, String> {
+    let sql_opt = get_sql_opt().await;
+    let quota = sql_opt
+        .get_user_quota(user_name)
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+
+    let default_bytes = Some(CONFIG.default_quota_bytes).filter(|n| *n > 0);
+    let default_files = Some(CONFIG.default_quota_files).filter(|n| *n > 0);
+
+    Ok(Usage {
+        user_name: user_name.to_string(),
+        used_bytes: quota.used_bytes,
+        file_count: quota.file_count,
+        max_bytes: quota.max_bytes.or(default_bytes),
+        max_files: quota.max_files.or(default_files),
+    })
+}
+
+lazy_static! {
+    static ref QUOTA_LOCKS: DashMap<String, Arc<Mutex<()>>> = DashMap::new();
+}
+
+// 同一用户的配额检查与随后的写入需在此锁内完成, 避免并发上传各自通过检查后合计超出配额
+pub async fn lock_user(user_name: &str) -> OwnedMutexGuard<()> {
+    let lock = Arc::clone(QUOTA_LOCKS.entry(user_name.to_string()).or_default().value());
+    lock.lock_owned().await
+}
+
+// 未完成的上传按声明大小与已写入字节数中的较大者预留容量, 并各计一个文件
+pub async fn check_quota(user_name: &str, extra_bytes: i64, extra_files: i32) -> Result<(), String> {
+    let usage = load_usage(user_name).await?;
+    let sql_opt = get_sql_opt().await;
+    let (pending_bytes, pending_files) = sql_opt
+        .get_pending_uploads(user_name)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let used_bytes = usage.used_bytes.saturating_add(pending_bytes);
+    if let Some(max_bytes) = usage.max_bytes
+        && used_bytes.saturating_add(extra_bytes) > max_bytes
+    {
+        return Err(format!(
+            "storage quota exceeded: {} of {} bytes used or reserved",
+            used_bytes, max_bytes
+        ));
+    }
+
+    let file_count = usage.file_count.saturating_add(pending_files);
+    if let Some(max_files) = usage.max_files
+        && file_count.saturating_add(extra_files) > max_files
+    {
+        return Err(format!(
+            "file count quota exceeded: {} of {} files used or reserved",
+            file_count, max_files
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct GetUsageReq {
+    // 仅管理员可以查询其他用户
+    user_name: Option<String>,
+}
+
+pub async fn get_usage(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<GetUsageReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let caller = match block.user_name() {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}")),
+    };
+
+    let user_name = match req.user_name {
+        Some(user_name) if user_name != caller && !CONFIG.is_admin(&caller) => {
+            return make_failed_resp!(code: ErrorCode::PermissionDenied, payload: "permission denied");
+        }
+        Some(user_name) => user_name,
+        None => caller,
+    };
+
+    let usage = match load_usage(&user_name).await {
+        Ok(usage) => usage,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    match serde_json::to_string(&usage) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{
+    config::CONFIG,
+    engine::http::{ParamType, Route},
+};
+
+// HTTPS 网关的 REST 路由, handler 名与 main.rs 中注册的一致
+pub fn routes() -> Vec<Route> {
+    vec![
+        Route::new("POST", "/register", "register"),
+        Route::new("POST", "/login", "login"),
+        Route::new("POST", "/refresh", "refresh").no_input(),
+        Route::new("GET", "/usage", "get_usage"),
+        Route::new("GET", "/files", "list_file")
+            .query_param("min_size", ParamType::Int)
+            .query_param("max_size", ParamType::Int)
+            .query_param("desc", ParamType::Bool)
+            .query_param("limit", ParamType::Int),
+        Route::new("GET", "/files/search", "search_files")
+            .query_param("mine", ParamType::Bool)
+            .query_param("limit", ParamType::Int),
+        Route::new("POST", "/files", "presend"),
+        Route::new("PUT", "/files/{file_id:int}/blocks/{block_id:int}", "send")
+            .raw_request("block_payload", Some("block_checksum"), CONFIG.max_block_size),
+        Route::new("POST", "/files/{file_id:int}/finish", "finish"),
+        Route::new("GET", "/files/{file_id:int}", "get_file_info"),
+        Route::new("PATCH", "/files/{file_id:int}", "update_file_meta"),
+        Route::new("DELETE", "/files/{file_id:int}", "delete_file"),
+        Route::new("POST", "/files/{file_id:int}/rename", "rename_file"),
+        Route::new("GET", "/files/{file_id:int}/blocks", "get_block_ids"),
+        Route::new("GET", "/files/{file_id:int}/manifest", "get_manifest"),
+        Route::new("GET", "/files/{file_id:int}/content", "download_file").raw_stream("data"),
+        Route::new("GET", "/files/{file_id:int}/range", "read_range")
+            .query_param("offset", ParamType::Int)
+            .query_param("length", ParamType::Int)
+            .raw_response("data"),
+        Route::new("GET", "/files/{file_id:int}/versions", "list_versions"),
+        Route::new("POST", "/files/{file_id:int}/restore_version", "restore_version"),
+        Route::new("GET", "/blocks/{block_id:int}", "get_block").raw_response("block_data"),
+        Route::new("GET", "/trash", "list_trash").no_input(),
+        Route::new("POST", "/trash/{file_id:int}/restore", "restore_file"),
+        Route::new("DELETE", "/trash/{file_id:int}", "purge_file"),
+        Route::new("GET", "/dirs", "list_dir"),
+        Route::new("POST", "/dirs", "mkdir"),
+        Route::new("POST", "/dirs/move", "move"),
+        Route::new("POST", "/dirs/rename", "rename"),
+        Route::new("DELETE", "/dirs", "rmdir").query_param("recursive", ParamType::Bool),
+    ]
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG,
+    control_block::parse_input,
+    db::{SearchHit, SearchQuery, get_sql_opt},
+    engine::return_code::{ErrorCode, ReturnCode},
+    handler::info::escape_like,
+    make_failed_resp, make_success_resp,
+};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 500;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SearchFilesReq {
+    // 对文件名、描述和标签键值做全文检索
+    query: String,
+    // 精确匹配的标签键值
+    tags: BTreeMap<String, String>,
+    extension: Option<String>,
+    created_after: Option<NaiveDateTime>,
+    created_before: Option<NaiveDateTime>,
+    // 只搜索自己上传的文件, 不含没有所有者的旧文件
+    mine: bool,
+    limit: u32,
+}
+
+#[derive(Serialize)]
+pub struct SearchFilesResp {
+    results: Vec<SearchHit>,
+}
+
+// 结果限于调用者可访问的文件: 自己的文件和没有所有者的旧文件, 管理员可搜索全部文件
+pub async fn search_files(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<SearchFilesReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let user_name = match block.user_name() {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}")),
+    };
+    let owner = req.mine.then(|| user_name.clone());
+    let visible_to = (!CONFIG.is_admin(&user_name)).then_some(user_name);
+
+    let text = Some(req.query.trim().to_string()).filter(|text| !text.is_empty());
+    let extension_pattern = req
+        .extension
+        .map(|ext| ext.trim_start_matches('.').to_string())
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| format!("%.{}", escape_like(&ext)));
+
+    let query = SearchQuery {
+        text,
+        tags: req.tags.into_iter().collect(),
+        extension_pattern,
+        owner,
+        visible_to,
+        created_after: req.created_after,
+        created_before: req.created_before,
+        limit: match req.limit {
+            0 => DEFAULT_LIMIT,
+            n => n.min(MAX_LIMIT),
+        },
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let results = match sql_opt.search_files(&query).await {
+        Ok(results) => results,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    match serde_json::to_string(&SearchFilesResp { results }) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
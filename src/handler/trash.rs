@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG,
+    control_block::parse_input,
+    db::{FILE_COMPLETED, FILE_DELETED, FILE_SUPERSEDED, FileInfo, get_sql_opt},
+    engine::return_code::{ErrorCode, ReturnCode},
+    handler::{
+        dir,
+        event::{self, EventKind},
+        info::check_file_owner,
+    },
+    make_failed_resp, make_success_resp, storage,
+};
+
+const PURGE_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize)]
+pub struct ListTrashResp {
+    file_info: Vec<FileInfo>,
+}
+
+pub async fn list_trash(payload: String) -> ReturnCode {
+    let (block, _) = match parse_input::<i32>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = block.check_jwt() {
+        return make_failed_resp!(payload: e);
+    }
+
+    let user_name = match block.user_name() {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}")),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let files = if CONFIG.is_admin(&user_name) {
+        sql_opt.get_all_trash().await
+    } else {
+        sql_opt.get_trash(&user_name).await
+    };
+
+    let resp = match files {
+        Ok(file_info) => ListTrashResp { file_info },
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    match serde_json::to_string(&resp) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TrashFileReq {
+    file_id: i32,
+}
+
+async fn load_trashed(payload: &str) -> Result<FileInfo, String> {
+    let (block, req) = parse_input::<TrashFileReq>(payload)?;
+
+    let sql_opt = get_sql_opt().await;
+    let file_info = sql_opt
+        .get_file_info_by_id(req.file_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if file_info.file_status != FILE_DELETED {
+        return Err("file is not in trash".to_string());
+    }
+
+    // 没有所有者的旧文件由删除它的用户恢复或清除
+    if file_info.owner.is_none() {
+        block.check_jwt()?;
+        let user_name = block.user_name().map_err(|e| format!("{}: invalid jwt: {e}", ErrorCode::Unauthorized))?;
+        if file_info.deleted_by.as_deref() == Some(user_name.as_str()) {
+            return Ok(file_info);
+        }
+    }
+
+    check_file_owner(&block, &file_info)?;
+    Ok(file_info)
+}
+
+pub async fn restore_file(payload: String) -> ReturnCode {
+    let file_info = match load_trashed(&payload).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+
+    // 原目录已被删除时恢复到根目录
+    let dir_id = match sql_opt.directory_exists(file_info.dir_id).await {
+        Ok(true) => file_info.dir_id,
+        Ok(false) => dir::ROOT_DIR_ID,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    // 已有当前版本时恢复为历史版本, 不占用文件名
+    let has_current = match sql_opt.get_file_versions(file_info.logical_id).await {
+        Ok(versions) => versions.iter().any(|v| v.file_status == FILE_COMPLETED),
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+    if !has_current
+        && let Err(e) = dir::check_name_free(dir_id, &file_info.file_name).await
+    {
+        return make_failed_resp!(payload: e);
+    }
+
+    match sql_opt.restore_file_info(file_info.id, file_info.logical_id, dir_id).await {
+        Ok(true) => {
+            event::publish_file(&file_info, EventKind::FileRestored {
+                file_id: file_info.id,
+                dir_id,
+            });
+            make_success_resp!(payload: dir_id)
+        }
+        Ok(false) => {
+            info!("file {} restored as a previous version of {}", file_info.id, file_info.logical_id);
+            make_success_resp!(payload: dir_id)
+        }
+        Err(e) => make_failed_resp!(payload: dir::name_error(e, &file_info.file_name)),
+    }
+}
+
+pub async fn purge_file(payload: String) -> ReturnCode {
+    let file_info = match load_trashed(&payload).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    match purge_with_versions(&file_info).await {
+        Ok(_) => make_success_resp!(),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+// 彻底删除回收站中的文件及其全部历史版本
+async fn purge_with_versions(file_info: &FileInfo) -> Result<(), String> {
+    let sql_opt = get_sql_opt().await;
+    let versions = sql_opt
+        .get_file_versions(file_info.logical_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for version in versions.iter().filter(|v| v.file_status == FILE_SUPERSEDED) {
+        storage::purge_file(version.id).await?;
+    }
+    storage::purge_file(file_info.id).await?;
+
+    event::publish_file(file_info, EventKind::FilePurged { file_id: file_info.id });
+    Ok(())
+}
+
+pub async fn run_background() {
+    if CONFIG.trash_retention_days == 0 {
+        info!("trash auto purge disabled");
+        return;
+    }
+
+    loop {
+        tokio::time::sleep(PURGE_CHECK_INTERVAL).await;
+
+        let sql_opt = get_sql_opt().await;
+        let expired = match sql_opt.get_expired_trash(CONFIG.trash_retention_days).await {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("failed to load expired trash: {}", e);
+                continue;
+            }
+        };
+
+        for file_info in expired {
+            if let Err(e) = purge_with_versions(&file_info).await {
+                warn!("failed to purge file {}: {}", file_info.id, e);
+            }
+        }
+    }
+}
@@ -1,18 +1,30 @@
 use crate::{
+    config::CONFIG,
     control_block::parse_input,
-    db::get_sql_opt,
-    engine::return_code::ReturnCode,
+    db::{get_sql_opt, FILE_COMPLETED, FILE_UPLOADING},
+    engine::return_code::{ErrorCode, ReturnCode},
+    handler::{
+        dir,
+        event::{self, EventKind},
+        info::check_file_owner,
+        quota,
+        version,
+    },
     make_failed_resp, make_success_resp,
+    storage,
+    utils::checksum,
 };
-use crc::{CRC_32_ISO_HDLC, Crc};
 use serde::Deserialize;
-use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
 #[derive(Deserialize)]
 struct PresendReq {
     pub file_name: String,
     pub file_size: u64,
+    #[serde(default)]
+    pub dir_path: Option<String>,
+    #[serde(default)]
+    pub target_file_id: Option<i32>,
 }
 
 pub async fn presend(payload: String) -> ReturnCode {
@@ -24,21 +36,82 @@ pub async fn presend(payload: String) -> ReturnCode {
     match control_block.validate_jwt() {
         Ok(rst) => {
             if !rst {
-                return make_failed_resp!(payload: "jwt expired");
+                return make_failed_resp!(code: ErrorCode::Unauthorized, payload: "jwt expired");
             }
         }
         Err(e) => {
-            return make_failed_resp!(payload: format!("invalid jwt: {e}"));
+            return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}"));
         }
     }
 
-    let file_name = &content.file_name;
+    let user_name = match control_block.user_name() {
+        Ok(user_name) => user_name,
+        Err(e) => return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}")),
+    };
+
     let file_size = content.file_size;
 
+    if CONFIG.max_file_size > 0 && file_size > CONFIG.max_file_size {
+        return make_failed_resp!(
+            code: ErrorCode::FileTooLarge,
+            payload: format!("file size exceeds {} bytes", CONFIG.max_file_size)
+        );
+    }
+
     let sql_opt = get_sql_opt().await;
 
+    // 指定 target_file_id 时作为该文件的新版本上传, 沿用其名字、目录和所有者
+    let (file_name, dir_id, owner, version_of) = match content.target_file_id {
+        Some(target_id) => {
+            let target = match sql_opt.get_file_info_by_id(target_id).await {
+                Ok(info) => info,
+                Err(e) => return make_failed_resp!(payload: e),
+            };
+
+            if target.file_status != FILE_COMPLETED {
+                return make_failed_resp!(payload: "target file is not the current version");
+            }
+
+            if let Err(e) = check_file_owner(&control_block, &target) {
+                return make_failed_resp!(payload: e);
+            }
+
+            let owner = target.owner.unwrap_or(user_name);
+            (target.file_name, target.dir_id, owner, Some(target.logical_id))
+        }
+        None => {
+            if let Err(e) = dir::validate_name(&content.file_name) {
+                return make_failed_resp!(payload: e);
+            }
+
+            let dir_id = match &content.dir_path {
+                Some(path) => match dir::resolve_dir(path).await {
+                    Ok(id) => id,
+                    Err(e) => return make_failed_resp!(payload: e),
+                },
+                None => dir::ROOT_DIR_ID,
+            };
+
+            if let Err(e) = dir::check_name_free(dir_id, &content.file_name).await {
+                return make_failed_resp!(payload: format!("{e}, set target_file_id to upload a new version"));
+            }
+
+            (content.file_name, dir_id, user_name, None)
+        }
+    };
+
+    let _quota_lock = quota::lock_user(&owner).await;
+    if let Err(e) = quota::check_quota(&owner, file_size as i64, 1).await {
+        return make_failed_resp!(payload: e);
+    }
+
+    let wrapped_key = match storage::new_wrapped_data_key() {
+        Ok(key) => key,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
     let file_id = match sql_opt
-        .init_file_info(file_name, file_size)
+        .init_file_info(&file_name, file_size, wrapped_key.as_deref(), dir_id, &owner, version_of)
         .await
     {
         Err(e) => return make_failed_resp!(payload: e),
@@ -56,6 +129,11 @@ struct SendReq {
     pub block_payload: Vec<u8>,
 }
 
+// send 请求帧的上限: 块以 JSON 数字数组编码 (每字节最多 4 字符), 再经 base64 膨胀 4/3
+pub fn max_send_frame_size() -> usize {
+    CONFIG.max_block_size * 4 / 3 * 4 + 64 * 1024
+}
+
 fn make_block_name(file_id: u32, block_id: u64) -> String {
     let uuid = Uuid::new_v4();
     format!("./storage/{}-{}_{}", file_id, block_id, uuid)
@@ -72,11 +150,11 @@ pub async fn send(payload: String) -> ReturnCode {
     match control_block.validate_jwt() {
         Ok(rst) => {
             if !rst {
-                return make_failed_resp!(payload: "jwt expired");
+                return make_failed_resp!(code: ErrorCode::Unauthorized, payload: "jwt expired");
             }
         }
         Err(e) => {
-            return make_failed_resp!(payload: format!("invalid jwt: {e}"));
+            return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}"));
         }
     }
 
@@ -85,6 +163,13 @@ pub async fn send(payload: String) -> ReturnCode {
     let block_id = content.block_id;
     let block_payload = content.block_payload;
 
+    if block_payload.len() > CONFIG.max_block_size {
+        return make_failed_resp!(
+            code: ErrorCode::BlockTooLarge,
+            payload: format!("block size exceeds {} bytes", CONFIG.max_block_size)
+        );
+    }
+
     let block_name = make_block_name(file_id, block_id);
 
     if block_checksum != checksum(&block_payload) {
@@ -93,16 +178,52 @@ pub async fn send(payload: String) -> ReturnCode {
 
     let sql_opt = get_sql_opt().await;
 
-    let mut file = match tokio::fs::File::create(&block_name).await
-    {
-        Ok(f) => f,
+    let file_info = match sql_opt.get_file_info_by_id(file_id as i32).await {
+        Ok(info) => info,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if file_info.file_status != FILE_UPLOADING {
+        return make_failed_resp!(payload: "file is not being uploaded");
+    }
+
+    if let Err(e) = check_file_owner(&control_block, &file_info) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let data_key = match storage::file_data_key(&file_info) {
+        Ok(key) => key,
         Err(e) => return make_failed_resp!(payload: e),
     };
 
-    if let Err(e) = file.write_all(&block_payload).await {
-        return make_failed_resp!(payload: format!("write file err: {e}"));
+    if let Err(e) = storage::write_block(&block_name, data_key.as_ref(), file_id, block_id, &block_payload).await {
+        return make_failed_resp!(payload: e);
     }
 
+    // presend 已按声明大小预留容量, 写入超出声明大小时才需要额外的配额
+    let _quota_lock = match &file_info.owner {
+        Some(owner) => {
+            let quota_lock = quota::lock_user(owner).await;
+            let uploaded = match sql_opt.get_uploaded_bytes(file_info.id).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&block_name).await;
+                    return make_failed_resp!(payload: e);
+                }
+            };
+            let reserved = uploaded.max(file_info.file_size);
+            let extra = (uploaded + block_payload.len() as i64 - reserved).max(0);
+            if extra > 0
+                && let Err(e) = quota::check_quota(owner, extra, 0).await
+            {
+                let _ = tokio::fs::remove_file(&block_name).await;
+                return make_failed_resp!(payload: e);
+            }
+            Some(quota_lock)
+        }
+        None => None,
+    };
+
     if let Err(e) = sql_opt
         .write_block_info(
             file_id,
@@ -119,11 +240,6 @@ pub async fn send(payload: String) -> ReturnCode {
     make_success_resp!()
 }
 
-fn checksum(payload: &[u8]) -> u32 {
-    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-    crc.checksum(payload)
-}
-
 #[derive(Deserialize)]
 struct FinishReq {
     pub file_id: u32,
@@ -139,11 +255,11 @@ pub async fn finish(payload: String) -> ReturnCode {
     match control_block.validate_jwt() {
         Ok(rst) => {
             if !rst {
-                return make_failed_resp!(payload: "jwt expired");
+                return make_failed_resp!(code: ErrorCode::Unauthorized, payload: "jwt expired");
             }
         }
         Err(e) => {
-            return make_failed_resp!(payload: format!("invalid jwt: {e}"));
+            return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}"));
         }
     }
 
@@ -152,9 +268,33 @@ pub async fn finish(payload: String) -> ReturnCode {
 
     let sql_opt = get_sql_opt().await;
 
+    let file_info = match sql_opt.get_file_info_by_id(file_id as i32).await {
+        Ok(info) => info,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if file_info.file_status != FILE_UPLOADING {
+        return make_failed_resp!(payload: "file is not being uploaded");
+    }
+
+    if let Err(e) = check_file_owner(&control_block, &file_info) {
+        return make_failed_resp!(payload: e);
+    }
+
     if let Err(e) = sql_opt.finish_file_info(file_id, file_checksum).await {
         return make_failed_resp!(payload: e);
     }
 
+    version::prune_versions(file_info.logical_id).await;
+
+    event::publish_file(&file_info, EventKind::FileCompleted {
+        file_id: file_info.id,
+        file_name: file_info.file_name.clone(),
+        file_size: file_info.file_size,
+    });
+    if let Some(owner) = &file_info.owner {
+        event::check_quota_warning(owner).await;
+    }
+
     make_success_resp!()
 }
\ No newline at end of file
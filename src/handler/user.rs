@@ -1,7 +1,59 @@
-use log::info;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use log::{info, warn};
 use serde::Deserialize;
 
-use crate::{control_block::{parse_input, ControlBlock}, db::get_sql_opt, engine::return_code::*, make_failed_resp, make_success_resp};
+use crate::{config::CONFIG, control_block::{parse_input, ControlBlock}, db::get_sql_opt, engine::{limiter::RateLimiter, return_code::*}, make_failed_resp, make_success_resp};
+
+const MAX_TRACKED_USERS: usize = 10000;
+
+struct LoginFailures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+lazy_static! {
+    static ref LOGIN_LIMITER: Option<RateLimiter> = (CONFIG.login_rate_per_min > 0.0)
+        .then(|| RateLimiter::new(CONFIG.login_rate_per_min / 60.0, CONFIG.login_burst));
+    static ref LOGIN_FAILURES: DashMap<String, LoginFailures> = DashMap::new();
+}
+
+// 返回剩余锁定时间
+fn lockout_remaining(user_name: &str) -> Option<Duration> {
+    let failures = LOGIN_FAILURES.get(user_name)?;
+    let locked_until = failures.locked_until?;
+    locked_until.checked_duration_since(Instant::now())
+}
+
+// 连续失败达到上限后锁定账号, 之后每次失败锁定时间翻倍
+fn record_login_failure(user_name: &str) {
+    if LOGIN_FAILURES.len() > MAX_TRACKED_USERS {
+        let max_lockout = Duration::from_secs(CONFIG.login_max_lockout_secs);
+        LOGIN_FAILURES.retain(|_, failures| failures.last_failure.elapsed() < max_lockout);
+    }
+
+    let now = Instant::now();
+    let mut failures = LOGIN_FAILURES.entry(user_name.to_string()).or_insert(LoginFailures {
+        count: 0,
+        last_failure: now,
+        locked_until: None,
+    });
+    failures.count += 1;
+    failures.last_failure = now;
+
+    if CONFIG.login_max_failures > 0 && failures.count >= CONFIG.login_max_failures {
+        let exp = (failures.count - CONFIG.login_max_failures).min(16);
+        let secs = CONFIG
+            .login_lockout_secs
+            .saturating_mul(1 << exp)
+            .min(CONFIG.login_max_lockout_secs);
+        failures.locked_until = Some(now + Duration::from_secs(secs));
+        warn!("user {} locked for {}s after {} failed logins", user_name, secs, failures.count);
+    }
+}
 
 pub async fn ping(payload: String) -> ReturnCode {
     make_success_resp!(payload: format!("payload: {{{payload}}}"))
@@ -47,6 +99,19 @@ pub async fn login(payload: String) -> ReturnCode {
         Err(e) => return make_failed_resp!(payload: e),
     };
 
+    if let Some(limiter) = LOGIN_LIMITER.as_ref()
+        && !limiter.try_acquire(&content.user_name)
+    {
+        return make_failed_resp!(code: ErrorCode::RateLimited, payload: "too many login attempts");
+    }
+
+    if let Some(remaining) = lockout_remaining(&content.user_name) {
+        return make_failed_resp!(
+            code: ErrorCode::AccountLocked,
+            payload: format!("account locked, retry after {}s", remaining.as_secs() + 1)
+        );
+    }
+
     let rst = sql_opt
         .login(&content.user_name, &content.password)
         .await;
@@ -56,8 +121,10 @@ pub async fn login(payload: String) -> ReturnCode {
     match rst {
         Ok(rst) => {
             if !rst {
-                return make_failed_resp!(payload: "login failed");
+                record_login_failure(&content.user_name);
+                return make_failed_resp!(code: ErrorCode::Unauthorized, payload: "login failed");
             }
+            LOGIN_FAILURES.remove(&content.user_name);
             info!("user {} login", content.user_name);
         },
         Err(e) => {
@@ -70,6 +137,18 @@ pub async fn login(payload: String) -> ReturnCode {
     make_success_resp!(block: block)
 }
 
+// 供 Engine 在 mTLS 握手后把客户端证书映射到用户
+pub async fn cert_user(subjects: Vec<String>) -> Option<String> {
+    let sql_opt = get_sql_opt().await;
+    match sql_opt.get_user_by_cert_subjects(&subjects).await {
+        Ok(user_name) => user_name,
+        Err(e) => {
+            warn!("lookup cert user err: {}", e);
+            None
+        }
+    }
+}
+
 pub async fn refresh(payload: String) -> ReturnCode {
     let (mut block, _) = match parse_input::<i32>(&payload) {
         Ok(rst) => rst,
@@ -79,7 +158,7 @@ pub async fn refresh(payload: String) -> ReturnCode {
     match block.validate_jwt() {
         Ok(rst) => {
             if !rst {
-                return make_failed_resp!(payload: "jwt expired");
+                return make_failed_resp!(code: ErrorCode::Unauthorized, payload: "jwt expired");
             } else {
                 if let Err(e) = block.refresh_jwt() {
                     return make_failed_resp!(payload: format!("refresh jwt err: {e}"));
@@ -87,7 +166,7 @@ pub async fn refresh(payload: String) -> ReturnCode {
             }
         }
         Err(e) => {
-            return make_failed_resp!(payload: format!("invalid jwt: {e}"));
+            return make_failed_resp!(code: ErrorCode::Unauthorized, payload: format!("invalid jwt: {e}"));
         }
     }
 
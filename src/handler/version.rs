@@ -0,0 +1,192 @@
+use std::time;
+
+use chrono::{Duration, Local};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::CONFIG,
+    control_block::parse_input,
+    db::{FILE_COMPLETED, FILE_SUPERSEDED, FileInfo, get_sql_opt},
+    engine::return_code::ReturnCode,
+    handler::{
+        dir,
+        event::{self, EventKind},
+        info::check_file_owner,
+    },
+    make_failed_resp, make_success_resp, storage,
+};
+
+#[derive(Deserialize)]
+pub struct ListVersionsReq {
+    file_id: i32,
+}
+
+#[derive(Serialize)]
+pub struct ListVersionsResp {
+    logical_id: i32,
+    versions: Vec<FileInfo>,
+}
+
+pub async fn list_versions(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<ListVersionsReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if let Err(e) = check_file_owner(&block, &file_info) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let versions = match sql_opt.get_file_versions(file_info.logical_id).await {
+        Ok(versions) => versions,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let resp = ListVersionsResp {
+        logical_id: file_info.logical_id,
+        versions,
+    };
+
+    match serde_json::to_string(&resp) {
+        Ok(resp) => make_success_resp!(payload: resp),
+        Err(e) => make_failed_resp!(payload: e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreVersionReq {
+    file_id: i32,
+}
+
+pub async fn restore_version(payload: String) -> ReturnCode {
+    let (block, req) = match parse_input::<RestoreVersionReq>(&payload) {
+        Ok(rst) => rst,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let file_info = match sql_opt.get_file_info_by_id(req.file_id).await {
+        Ok(file_info) => file_info,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    if file_info.file_status != FILE_SUPERSEDED {
+        return make_failed_resp!(payload: "not a previous version");
+    }
+
+    if let Err(e) = check_file_owner(&block, &file_info) {
+        return make_failed_resp!(payload: e);
+    }
+
+    let versions = match sql_opt.get_file_versions(file_info.logical_id).await {
+        Ok(versions) => versions,
+        Err(e) => return make_failed_resp!(payload: e),
+    };
+
+    // 沿用当前版本的位置; 若当前版本已不存在, 则恢复到原位置
+    let current = versions.iter().find(|v| v.file_status == FILE_COMPLETED);
+    let (dir_id, file_name) = match current {
+        Some(current) => (current.dir_id, current.file_name.clone()),
+        None => {
+            if let Err(e) = dir::check_name_free(file_info.dir_id, &file_info.file_name).await {
+                return make_failed_resp!(payload: e);
+            }
+            (file_info.dir_id, file_info.file_name.clone())
+        }
+    };
+
+    if let Err(e) = sql_opt
+        .restore_version(file_info.id, file_info.logical_id, dir_id, &file_name)
+        .await
+    {
+        return make_failed_resp!(payload: e);
+    }
+
+    info!("file {} restored to version {}", file_info.logical_id, file_info.version);
+
+    // 被替换的当前版本对订阅者而言已删除, 恢复的版本以新位置重新出现
+    if let Some(current) = current {
+        event::publish_file(current, EventKind::FileDeleted {
+            file_id: current.id,
+            file_name: current.file_name.clone(),
+        });
+    }
+    event::publish_file(&file_info, EventKind::FileCompleted {
+        file_id: file_info.id,
+        file_name,
+        file_size: file_info.file_size,
+    });
+    make_success_resp!()
+}
+
+const PRUNE_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(3600);
+
+// 按保留策略清理历史版本: 保留最近 N 个版本 / 保留 N 天内的版本
+pub async fn prune_versions(logical_id: i32) {
+    let keep_last = CONFIG.version_keep_last as usize;
+    let keep_days = CONFIG.version_keep_days;
+    if keep_last == 0 && keep_days == 0 {
+        return;
+    }
+
+    if logical_id <= 0 {
+        warn!("skip pruning versions of invalid logical_id {}", logical_id);
+        return;
+    }
+
+    let sql_opt = get_sql_opt().await;
+    let versions = match sql_opt.get_file_versions(logical_id).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            warn!("failed to load versions of file {}: {}", logical_id, e);
+            return;
+        }
+    };
+
+    let deadline = (Local::now() - Duration::days(keep_days as i64)).naive_local();
+    for (i, version) in versions.iter().enumerate() {
+        if version.file_status != FILE_SUPERSEDED {
+            continue;
+        }
+
+        let too_many = keep_last > 0 && i >= keep_last;
+        let too_old = keep_days > 0 && version.created_at < deadline;
+        if (too_many || too_old)
+            && let Err(e) = storage::purge_file(version.id).await
+        {
+            warn!("failed to purge version {} of file {}: {}", version.version, logical_id, e);
+        }
+    }
+}
+
+// 定期清理, 使 VERSION_KEEP_DAYS 对不再上传新版本的文件也生效
+pub async fn run_background() {
+    if CONFIG.version_keep_last == 0 && CONFIG.version_keep_days == 0 {
+        info!("version pruning disabled");
+        return;
+    }
+
+    loop {
+        tokio::time::sleep(PRUNE_CHECK_INTERVAL).await;
+
+        let sql_opt = get_sql_opt().await;
+        let logical_ids = match sql_opt.get_versioned_logical_ids().await {
+            Ok(ids) => ids,
+            Err(e) => {
+                warn!("failed to load versioned files: {}", e);
+                continue;
+            }
+        };
+
+        for logical_id in logical_ids {
+            prune_versions(logical_id).await;
+        }
+    }
+}
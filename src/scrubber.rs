@@ -0,0 +1,202 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::ErrorKind,
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use log::*;
+use serde::Serialize;
+
+use crate::{
+    config::CONFIG,
+    db::{FileBlock, get_sql_opt},
+    storage,
+    utils::checksum,
+};
+
+pub const BLOCK_OK: i32 = 0;
+pub const BLOCK_CORRUPTED: i32 = 1;
+pub const BLOCK_MISSING: i32 = 2;
+
+const PAGE_SIZE: u32 = 256;
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ScrubReport {
+    pub checked_blocks: u64,
+    pub checked_bytes: u64,
+    pub corrupted_blocks: Vec<i32>,
+    pub missing_blocks: Vec<i32>,
+    pub affected_file_ids: BTreeSet<i32>,
+}
+
+// 当前或最近一次巡检的状态, 供管理员查询
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ScrubStatus {
+    pub running: bool,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub last_report: Option<ScrubReport>,
+    pub last_error: Option<String>,
+}
+
+lazy_static! {
+    static ref SCRUB_STATUS: Mutex<ScrubStatus> = Mutex::new(ScrubStatus::default());
+}
+
+pub fn status() -> ScrubStatus {
+    SCRUB_STATUS.lock().unwrap().clone()
+}
+
+fn begin() -> Result<(), String> {
+    let mut status = SCRUB_STATUS.lock().unwrap();
+    if status.running {
+        return Err("scrub already running".to_string());
+    }
+    status.running = true;
+    status.started_at = Some(Utc::now().naive_utc());
+    status.finished_at = None;
+    Ok(())
+}
+
+async fn run(bytes_per_sec: u64) -> Result<ScrubReport, String> {
+    let rst = scrub_all(bytes_per_sec).await;
+
+    let mut status = SCRUB_STATUS.lock().unwrap();
+    status.running = false;
+    status.finished_at = Some(Utc::now().naive_utc());
+    match &rst {
+        Ok(report) => {
+            status.last_report = Some(report.clone());
+            status.last_error = None;
+        }
+        Err(e) => status.last_error = Some(e.clone()),
+    }
+    rst
+}
+
+// 遍历 file_block, 重新计算每个块的 CRC32 与大小, 并把结果写回 block_status
+pub async fn scrub(bytes_per_sec: u64) -> Result<ScrubReport, String> {
+    begin()?;
+    run(bytes_per_sec).await
+}
+
+// 在后台启动巡检并立即返回, 结果通过 status 查询
+pub fn start(bytes_per_sec: u64) -> Result<ScrubStatus, String> {
+    begin()?;
+    tokio::spawn(async move {
+        match run(bytes_per_sec).await {
+            Ok(report) => info!("scrub finished: {} blocks checked", report.checked_blocks),
+            Err(e) => warn!("scrub failed: {}", e),
+        }
+    });
+    Ok(status())
+}
+
+async fn scrub_all(bytes_per_sec: u64) -> Result<ScrubReport, String> {
+    let sql_opt = get_sql_opt().await;
+    let mut report = ScrubReport::default();
+    let mut data_keys: HashMap<i32, Option<[u8; 32]>> = HashMap::new();
+    let mut last_id = 0;
+
+    loop {
+        let blocks = sql_opt
+            .get_block_infos_after(last_id, PAGE_SIZE)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(last) = blocks.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for block in blocks {
+            if !data_keys.contains_key(&block.file_id) {
+                let data_key = match sql_opt.get_file_info_by_id(block.file_id).await {
+                    Ok(file_info) => storage::file_data_key(&file_info),
+                    Err(e) => Err(e.to_string()),
+                };
+                match data_key {
+                    Ok(key) => {
+                        data_keys.insert(block.file_id, key);
+                    }
+                    Err(e) => {
+                        warn!("scrub skip block {}: {}", block.id, e);
+                        continue;
+                    }
+                }
+            }
+
+            let (block_status, n) = verify_block(&block, data_keys[&block.file_id].as_ref()).await;
+            report.checked_blocks += 1;
+            report.checked_bytes += n;
+
+            match block_status {
+                BLOCK_CORRUPTED => report.corrupted_blocks.push(block.id),
+                BLOCK_MISSING => report.missing_blocks.push(block.id),
+                _ => {}
+            }
+            if block_status != BLOCK_OK {
+                warn!("block {} of file {} is {}", block.id, block.file_id,
+                    if block_status == BLOCK_MISSING { "missing" } else { "corrupted" });
+                report.affected_file_ids.insert(block.file_id);
+            }
+
+            if let Err(e) = sql_opt.set_block_status(block.id, block_status).await {
+                warn!("failed to update status of block {}: {}", block.id, e);
+            }
+
+            if bytes_per_sec > 0 && n > 0 {
+                tokio::time::sleep(Duration::from_secs_f64(n as f64 / bytes_per_sec as f64)).await;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+async fn verify_block(block: &FileBlock, data_key: Option<&[u8; 32]>) -> (i32, u64) {
+    let data = match tokio::fs::read(&block.block_name).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return (BLOCK_MISSING, 0),
+        Err(e) => {
+            warn!("read block {} err: {}", block.id, e);
+            return (BLOCK_CORRUPTED, 0);
+        }
+    };
+    let n = data.len() as u64;
+
+    let data = match storage::decode_block(block, data_key, data) {
+        Ok(data) => data,
+        Err(_) => return (BLOCK_CORRUPTED, n),
+    };
+
+    if data.len() as u32 != block.block_size || checksum(&data) != block.block_checksum {
+        return (BLOCK_CORRUPTED, n);
+    }
+
+    (BLOCK_OK, n)
+}
+
+pub async fn run_background() {
+    if CONFIG.scrub_interval_secs == 0 {
+        info!("background scrubber disabled");
+        return;
+    }
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(CONFIG.scrub_interval_secs)).await;
+        info!("background scrub started");
+        match scrub(CONFIG.scrub_bytes_per_sec).await {
+            Ok(report) => info!(
+                "background scrub finished: {} blocks checked, {} corrupted, {} missing, affected files {:?}",
+                report.checked_blocks,
+                report.corrupted_blocks.len(),
+                report.missing_blocks.len(),
+                report.affected_file_ids
+            ),
+            Err(e) => warn!("background scrub failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::ErrorKind;
+
+use log::*;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::CONFIG,
+    crypto::{self, block_aad},
+    db::{FileBlock, FileInfo, get_sql_opt},
+};
+
+// 为新文件生成数据密钥, 返回被主密钥包裹后的密钥; 未配置主密钥时不加密
+pub fn new_wrapped_data_key() -> Result<Option<Vec<u8>>, String> {
+    let Some(master_key) = CONFIG.master_key.as_ref() else {
+        return Ok(None);
+    };
+
+    let data_key = crypto::generate_data_key().map_err(|e| format!("generate data key err: {e}"))?;
+    let wrapped = crypto::wrap_data_key(master_key, &data_key)
+        .map_err(|e| format!("wrap data key err: {e}"))?;
+    Ok(Some(wrapped))
+}
+
+pub fn file_data_key(file_info: &FileInfo) -> Result<Option<[u8; 32]>, String> {
+    let Some(wrapped) = file_info.data_key.as_ref() else {
+        return Ok(None);
+    };
+
+    match CONFIG.master_key.as_ref() {
+        Some(master_key) => crypto::unwrap_data_key(master_key, wrapped).map(Some),
+        None => Err("file is encrypted but no master key is configured".to_string()),
+    }
+}
+
+pub async fn write_block(
+    block_name: &str,
+    data_key: Option<&[u8; 32]>,
+    file_id: u32,
+    block_id: u64,
+    payload: &[u8],
+) -> Result<(), String> {
+    let data = match data_key {
+        Some(key) => crypto::seal(key, &block_aad(file_id, block_id), payload)
+            .map_err(|e| format!("encrypt block err: {e}"))?,
+        None => payload.to_vec(),
+    };
+
+    let mut file = tokio::fs::File::create(block_name)
+        .await
+        .map_err(|e| format!("create file err: {e}"))?;
+    file.write_all(&data)
+        .await
+        .map_err(|e| format!("write file err: {e}"))?;
+    Ok(())
+}
+
+pub async fn read_block(block: &FileBlock, data_key: Option<&[u8; 32]>) -> Result<Vec<u8>, String> {
+    let data = tokio::fs::read(&block.block_name)
+        .await
+        .map_err(|e| format!("read file err: {e}"))?;
+
+    decode_block(block, data_key, data)
+}
+
+pub fn decode_block(block: &FileBlock, data_key: Option<&[u8; 32]>, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match data_key {
+        Some(key) => crypto::open(
+            key,
+            &block_aad(block.file_id as u32, block.block_id as u64),
+            &data,
+        ),
+        None => Ok(data),
+    }
+}
+
+// 删除块文件和所有相关记录, 释放存储空间
+pub async fn purge_file(file_id: i32) -> Result<(), String> {
+    let sql_opt = get_sql_opt().await;
+    let blocks = sql_opt
+        .get_blocks_by_file_id(file_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for block in &blocks {
+        if let Err(e) = tokio::fs::remove_file(&block.block_name).await
+            && e.kind() != ErrorKind::NotFound
+        {
+            return Err(format!("remove block {} err: {e}", block.id));
+        }
+    }
+
+    sql_opt
+        .delete_file_records(file_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    info!("file {} purged, {} blocks removed", file_id, blocks.len());
+    Ok(())
+}
+
+// 用新主密钥重新包裹所有数据密钥, 块数据本身不需要重写
+pub async fn rotate_master_key(new_master_key: &[u8; 32]) -> Result<usize, String> {
+    let Some(old_master_key) = CONFIG.master_key.as_ref() else {
+        return Err("no current master key configured".to_string());
+    };
+
+    let sql_opt = get_sql_opt().await;
+    let wrapped_keys = sql_opt
+        .get_wrapped_data_keys()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut rewrapped = Vec::with_capacity(wrapped_keys.len());
+    for (file_id, wrapped) in wrapped_keys {
+        let data_key = crypto::unwrap_data_key(old_master_key, &wrapped)
+            .map_err(|e| format!("file {file_id}: {e}"))?;
+        let wrapped = crypto::wrap_data_key(new_master_key, &data_key)
+            .map_err(|e| format!("file {file_id}: {e}"))?;
+        rewrapped.push((file_id, wrapped));
+    }
+
+    let n = rewrapped.len();
+    sql_opt
+        .update_wrapped_data_keys(rewrapped)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    info!("re-wrapped {} data keys", n);
+    Ok(n)
+}
@@ -1,9 +1,16 @@
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+pub const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
 #[macro_export]
 macro_rules! make_success_resp {
     // 明确区分 payload 和 block 的顺序
     (payload: $payload:expr, block: $block:expr) => {
         $crate::make_resp!(true, payload: $payload, block: $block)
     };
+    (payload: $payload:expr, stream: $stream:expr) => {
+        $crate::make_resp!(true, payload: $payload, stream: $stream)
+    };
     (payload: $payload:expr) => {
         $crate::make_resp!(true, payload: $payload)
     };
@@ -17,6 +24,9 @@ macro_rules! make_success_resp {
 
 #[macro_export]
 macro_rules! make_failed_resp {
+    (code: $code:expr, payload: $payload:expr) => {
+        $crate::make_resp!(false, payload: format!("{}: {}", $code, $payload))
+    };
     (payload: $payload:expr, block: $control_block:expr) => {
         $crate::make_resp!(false, payload: $payload, block: $control_block)
     };
@@ -40,6 +50,16 @@ macro_rules! make_resp {
             success: $success,
             payload: Some(payload),
             control_block: Some(block),
+            stream: None,
+        }
+    }};
+    ($success:expr, payload: $payload:expr, stream: $stream:expr) => {{
+        let payload = $payload.to_string();
+        $crate::engine::return_code::ReturnCode {
+            success: $success,
+            payload: Some(payload),
+            control_block: None,
+            stream: Some($stream),
         }
     }};
     ($success:expr, payload: $payload:expr) => {{
@@ -48,6 +68,7 @@ macro_rules! make_resp {
             success: $success,
             payload: Some(payload),
             control_block: None,
+            stream: None,
         }
     }};
     ($success:expr, block: $block:expr) => {{
@@ -56,6 +77,7 @@ macro_rules! make_resp {
             success: $success,
             payload: None,
             control_block: Some(block),
+            stream: None,
         }
     }};
     ($success:expr) => {{
@@ -63,8 +85,13 @@ macro_rules! make_resp {
             success: $success,
             payload: None,
             control_block: None,
+            stream: None,
         }
     }};
 }
 
 pub const END_MARK: &str = "\n\n\n";
+
+pub fn checksum(payload: &[u8]) -> u32 {
+    CRC32.checksum(payload)
+}
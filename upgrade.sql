@@ -0,0 +1,99 @@
+-- 从初始版本升级已有数据库时按顺序执行, 新建数据库直接使用 init.sql
+
+-- 块文件加密: 文件的数据密钥由主密钥包裹后保存; 已有文件保持明文
+ALTER TABLE `file_info`
+  ADD COLUMN `data_key` varbinary(128) DEFAULT NULL COMMENT '被主密钥包裹的数据密钥, NULL表示明文存储';
+
+-- 块完整性巡检: 记录每个块的校验结果
+ALTER TABLE `file_block`
+  ADD COLUMN `block_status` int NOT NULL DEFAULT 0 COMMENT '0:正常,1:损坏,2:丢失',
+  ADD COLUMN `checked_at` datetime DEFAULT NULL COMMENT '最近一次校验时间';
+
+-- 目录层级: 已有文件都位于根目录
+ALTER TABLE `file_info`
+  ADD COLUMN `dir_id` int NOT NULL DEFAULT 0 COMMENT '所在目录id, 0为根目录',
+  ADD KEY `idx_dir_id_file_name` (`dir_id`,`file_name`);
+
+CREATE TABLE `directory` (
+  `id` int NOT NULL AUTO_INCREMENT COMMENT 'id',
+  `parent_id` int NOT NULL DEFAULT 0 COMMENT '父目录id, 0为根目录',
+  `dir_name` varchar(255) NOT NULL COMMENT '目录名',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT 'CreatedAt',
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `uk_parent_id_dir_name` (`parent_id`,`dir_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='目录';
+
+-- 同一目录下当前版本的文件不能重名; 执行前需先处理已存在的重名文件
+ALTER TABLE `file_info`
+  ADD COLUMN `active_name` varchar(255) GENERATED ALWAYS AS (IF(`file_status` = 1, `file_name`, NULL)) STORED INVISIBLE COMMENT '当前版本的文件名, 用于目录内唯一约束',
+  ADD UNIQUE KEY `uk_dir_id_active_name` (`dir_id`,`active_name`);
+
+-- 文件所有者与元数据: 已有文件没有所有者
+ALTER TABLE `file_info`
+  ADD COLUMN `owner` varchar(255) DEFAULT NULL COMMENT '上传者用户名',
+  ADD COLUMN `mime_type` varchar(255) DEFAULT NULL COMMENT 'MIME类型',
+  ADD COLUMN `description` text COMMENT '文件描述';
+
+CREATE TABLE `file_tag` (
+  `id` int NOT NULL AUTO_INCREMENT COMMENT 'id',
+  `file_id` int NOT NULL COMMENT 'file_info id',
+  `tag_key` varchar(255) NOT NULL COMMENT '标签键',
+  `tag_value` varchar(255) NOT NULL COMMENT '标签值',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT 'CreatedAt',
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `uk_file_id_tag_key` (`file_id`,`tag_key`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='文件标签';
+
+-- 文件版本: 被新版本替换的文件状态为 3
+ALTER TABLE `file_info`
+  MODIFY COLUMN `file_status` int NOT NULL COMMENT '0:未完成,1:已完成,2:已删除,3:历史版本',
+  ADD COLUMN `logical_id` int NOT NULL DEFAULT 0 COMMENT '逻辑文件id, 同一文件的各版本相同',
+  ADD COLUMN `version` int NOT NULL DEFAULT 1 COMMENT '版本号',
+  ADD KEY `idx_logical_id_version` (`logical_id`,`version`);
+
+-- 旧记录的 logical_id 为 0, 回填为自身 id, 否则所有旧文件会被当作同一文件的多个版本
+UPDATE `file_info` SET `logical_id` = `id` WHERE `logical_id` = 0;
+
+-- 同一逻辑文件的版本号唯一; 新建文件在同一事务内回填 logical_id 前为 0, 不参与约束
+ALTER TABLE `file_info`
+  ADD COLUMN `version_key` int GENERATED ALWAYS AS (NULLIF(`logical_id`, 0)) STORED INVISIBLE COMMENT '新建文件回填 logical_id 前为 NULL, 用于版本号唯一约束',
+  ADD UNIQUE KEY `uk_version_key_version` (`version_key`,`version`);
+
+-- 回收站: 记录删除时间与删除者
+ALTER TABLE `file_info`
+  ADD COLUMN `deleted_at` datetime DEFAULT NULL COMMENT '删除时间',
+  ADD COLUMN `deleted_by` varchar(255) DEFAULT NULL COMMENT '删除者用户名',
+  ADD KEY `idx_file_status_deleted_at` (`file_status`,`deleted_at`);
+
+-- list_file 按名称、大小或创建时间排序分页
+ALTER TABLE `file_info`
+  ADD KEY `idx_file_status_file_name` (`file_status`,`file_name`,`id`),
+  ADD KEY `idx_file_status_file_size` (`file_status`,`file_size`,`id`),
+  ADD KEY `idx_file_status_created_at` (`file_status`,`created_at`,`id`);
+
+-- 全文检索文件名、描述和标签键值; 标签过滤按键值精确匹配
+ALTER TABLE `file_info` ADD FULLTEXT KEY `ft_file_name` (`file_name`) WITH PARSER ngram;
+ALTER TABLE `file_info` ADD FULLTEXT KEY `ft_description` (`description`) WITH PARSER ngram;
+ALTER TABLE `file_tag` ADD KEY `idx_tag_key_tag_value` (`tag_key`,`tag_value`);
+ALTER TABLE `file_tag` ADD FULLTEXT KEY `ft_tag` (`tag_key`,`tag_value`) WITH PARSER ngram;
+
+-- 用户配额: 已有文件没有所有者, 不计入任何用户的用量
+ALTER TABLE `file_info`
+  ADD COLUMN `stored_size` bigint DEFAULT NULL COMMENT '完成上传时计入配额的实际字节数';
+
+CREATE TABLE `user_quota` (
+  `user_name` varchar(255) NOT NULL COMMENT '用户名',
+  `max_bytes` bigint DEFAULT NULL COMMENT '容量上限Bytes, NULL表示使用默认配额',
+  `max_files` int DEFAULT NULL COMMENT '文件数上限, NULL表示使用默认配额',
+  `used_bytes` bigint NOT NULL DEFAULT 0 COMMENT '已用容量Bytes',
+  `file_count` int NOT NULL DEFAULT 0 COMMENT '已存文件数, 每个版本单独计数',
+  PRIMARY KEY (`user_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci COMMENT='用户配额与用量';
+
+-- 配额检查需要统计用户未完成的上传
+ALTER TABLE `file_info` ADD KEY `idx_owner_file_status` (`owner`,`file_status`);
+
+-- mTLS: 客户端证书的 CN 或 SAN 映射到用户
+ALTER TABLE `user`
+  ADD COLUMN `cert_subject` varchar(255) DEFAULT NULL COMMENT '客户端证书CN或SAN, 用于mTLS登录',
+  ADD UNIQUE KEY `uk_cert_subject` (`cert_subject`);